@@ -1,48 +1,569 @@
-use regex::Regex;
+use owo_colors::{OwoColorize, Style};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io::{BufReader, Read};
+use std::mem::take;
+use std::ops::Range;
 
-macro_rules! read_lines {
-    ($file:literal) => {
-        {
-            let file = std::fs::File::open($file).expect(&format!("error opening file {}", $file));
-            let buffer = std::io::BufReader::new(file);
-            std::io::BufRead::lines(buffer).map(|line| line.unwrap())
+/////////////////////////////////////////////////////////////////////
+// Machine State
+/////////////////////////////////////////////////////////////////////
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct MachineState {
+    enabled: bool,
+}
+
+impl Default for MachineState {
+    fn default() -> Self {
+        MachineState { enabled: true }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////
+// Instructions
+/////////////////////////////////////////////////////////////////////
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Effect {
+    Value(u64),
+    Control,
+}
+
+trait Instruction {
+    fn name(&self) -> &'static str;
+
+    // Returns None if arguments are invalid, the call is then ignored as corrupted memory
+    fn execute(&self, args: &[&str], state: &mut MachineState) -> Option<Effect>;
+}
+
+fn parse_operands<const N: usize>(args: &[&str], max_digits: usize) -> Option<[u64; N]> {
+    if args.len() != N {
+        return None;
+    }
+
+    let mut operands = [0; N];
+
+    for (operand, arg) in operands.iter_mut().zip(args) {
+        if arg.is_empty() || arg.len() > max_digits {
+            return None;
         }
-    };
+
+        *operand = arg.parse().ok()?;
+    }
+
+    Some(operands)
 }
 
-fn main() {
-    let re = Regex::new(r"((?<op>mul|do|don't)\(((?<a>[0-9]+),(?<b>[0-9]+))?\))").unwrap();
-    let mut enabled = true;
-    let mut part01 = 0;
-    let mut part02 = 0;
-
-    for line in read_lines!("day-03/input.txt") {
-        for expr in re.captures_iter(&line) {
-            match expr.name("op").unwrap().as_str() {
-                "mul" => {
-                    if let Some(a) = expr.name("a") {
-                        let a = a.as_str().parse::<i32>().unwrap();
-                        let b = expr.name("b").unwrap().as_str().parse::<i32>().unwrap();
-                        let r = a * b;
-
-                        part01 += r;
-
-                        if enabled {
-                            part02 += r;
-                        }
-                    }
+struct Mul;
+
+impl Instruction for Mul {
+    fn name(&self) -> &'static str {
+        "mul"
+    }
+
+    fn execute(&self, args: &[&str], _state: &mut MachineState) -> Option<Effect> {
+        let [a, b] = parse_operands(args, 3)?;
+        Some(Effect::Value(a * b))
+    }
+}
+
+struct Add;
+
+impl Instruction for Add {
+    fn name(&self) -> &'static str {
+        "add"
+    }
+
+    fn execute(&self, args: &[&str], _state: &mut MachineState) -> Option<Effect> {
+        let [a, b] = parse_operands(args, 3)?;
+        Some(Effect::Value(a + b))
+    }
+}
+
+struct Do;
+
+impl Instruction for Do {
+    fn name(&self) -> &'static str {
+        "do"
+    }
+
+    fn execute(&self, args: &[&str], state: &mut MachineState) -> Option<Effect> {
+        let [] = parse_operands(args, 0)?;
+        state.enabled = true;
+
+        Some(Effect::Control)
+    }
+}
+
+struct Dont;
+
+impl Instruction for Dont {
+    fn name(&self) -> &'static str {
+        "don't"
+    }
+
+    fn execute(&self, args: &[&str], state: &mut MachineState) -> Option<Effect> {
+        let [] = parse_operands(args, 0)?;
+        state.enabled = false;
+
+        Some(Effect::Control)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////
+// Instruction Set
+/////////////////////////////////////////////////////////////////////
+#[derive(Default)]
+struct InstructionSet {
+    instructions: HashMap<&'static str, Box<dyn Instruction>>,
+}
+
+impl InstructionSet {
+    fn register(&mut self, instruction: impl Instruction + 'static) {
+        self.instructions.insert(instruction.name(), Box::new(instruction));
+    }
+
+    fn get(&self, name: &str) -> Option<&dyn Instruction> {
+        self.instructions.get(name).map(|instr| instr.as_ref())
+    }
+
+    fn tokenizer(&self) -> Tokenizer {
+        Tokenizer::new(self.instructions.keys().copied().collect())
+    }
+
+    fn tokenize<R: Read>(&self, reader: R) -> impl Iterator<Item = Token> {
+        let mut tokenizer = self.tokenizer();
+
+        BufReader::new(reader).bytes()
+            .filter_map(move |byte| tokenizer.push(byte.unwrap()))
+    }
+}
+
+/////////////////////////////////////////////////////////////////////
+// Tokenizer
+/////////////////////////////////////////////////////////////////////
+// Longer argument lists are considered corrupted, this keeps the tokenizer memory bounded
+const MAX_ARGS_LEN: usize = 32;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Token {
+    name: &'static str,
+    args: String,
+    span: Range<usize>,
+}
+
+impl Token {
+    fn args(&self) -> Vec<&str> {
+        if self.args.is_empty() {
+            Vec::new()
+        } else {
+            self.args.split(',').collect()
+        }
+    }
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}({})", self.name, self.args)
+    }
+}
+
+struct Tokenizer {
+    names: Vec<&'static str>,
+    offset: usize,
+    pending: Vec<(usize, u8)>,
+    call: Option<(&'static str, usize)>,
+    args: String,
+}
+
+impl Tokenizer {
+    fn new(names: Vec<&'static str>) -> Self {
+        Tokenizer {
+            names,
+            offset: 0,
+            pending: Vec::new(),
+            call: None,
+            args: String::new(),
+        }
+    }
+
+    fn push(&mut self, byte: u8) -> Option<Token> {
+        let offset = self.offset;
+        self.offset += 1;
+
+        // Line breaks are not part of the memory, instructions may be split across lines
+        if byte == b'\n' || byte == b'\r' {
+            return None;
+        }
+
+        if let Some((name, start)) = self.call {
+            let after_digit = self.args.ends_with(|c: char| c.is_ascii_digit());
+
+            match byte {
+                b'0'..=b'9' if self.args.len() < MAX_ARGS_LEN => {
+                    self.args.push(byte as char);
+                    return None;
                 }
-                "do" => {
-                    enabled = true;
+                b',' if after_digit => {
+                    self.args.push(',');
+                    return None;
                 }
-                "don't" => {
-                    enabled = false;
+                b')' if after_digit || self.args.is_empty() => {
+                    self.call = None;
+
+                    return Some(Token {
+                        name,
+                        args: take(&mut self.args),
+                        span: start..offset + 1,
+                    });
+                }
+                _ => {
+                    self.call = None;
+                    self.args.clear();
                 }
-                _ => unreachable!(),
             }
         }
+
+        self.match_name(offset, byte);
+        None
+    }
+
+    fn match_name(&mut self, offset: usize, byte: u8) {
+        if byte == b'(' {
+            let call = (0..self.pending.len())
+                .find_map(|idx| self.find_name(&self.pending[idx..]).map(|name| (name, self.pending[idx].0)));
+
+            if call.is_some() {
+                self.call = call;
+                self.pending.clear();
+                return;
+            }
+        }
+
+        self.pending.push((offset, byte));
+
+        // Keep only the longest suffix which still may become an instruction name
+        while !self.pending.is_empty() && !self.is_name_prefix(&self.pending) {
+            self.pending.remove(0);
+        }
+    }
+
+    fn find_name(&self, bytes: &[(usize, u8)]) -> Option<&'static str> {
+        self.names.iter()
+            .find(|name| name.len() == bytes.len() && self.is_name_prefix_of(name, bytes))
+            .copied()
+    }
+
+    fn is_name_prefix(&self, bytes: &[(usize, u8)]) -> bool {
+        self.names.iter().any(|name| self.is_name_prefix_of(name, bytes))
+    }
+
+    fn is_name_prefix_of(&self, name: &str, bytes: &[(usize, u8)]) -> bool {
+        name.len() >= bytes.len() && name.bytes().zip(bytes).all(|(a, &(_, b))| a == b)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////
+// Machine
+/////////////////////////////////////////////////////////////////////
+#[derive(Debug)]
+struct Step {
+    token: Token,
+    enabled: bool,
+    effect: Effect,
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let state = if self.enabled { "enabled" } else { "disabled" };
+
+        match self.effect {
+            Effect::Value(value) => write!(f, "{} = {value} ({state})", self.token),
+            Effect::Control => write!(f, "{} => {state}", self.token),
+        }
+    }
+}
+
+struct Machine<'s> {
+    instructions: &'s InstructionSet,
+    state: MachineState,
+}
+
+impl<'s> Machine<'s> {
+    fn new(instructions: &'s InstructionSet) -> Self {
+        Machine { instructions, state: MachineState::default() }
+    }
+
+    fn execute(&mut self, token: Token) -> Option<Step> {
+        let instruction = self.instructions.get(token.name)?;
+        let effect = instruction.execute(&token.args(), &mut self.state)?;
+
+        Some(Step {
+            token,
+            enabled: self.state.enabled,
+            effect,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct Totals {
+    part01: u64,
+    part02: u64,
+}
+
+impl Totals {
+    fn add(&mut self, step: &Step) {
+        if let Effect::Value(value) = step.effect {
+            self.part01 += value;
+
+            if step.enabled {
+                self.part02 += value;
+            }
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////
+// Memory Dump
+/////////////////////////////////////////////////////////////////////
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum RegionKind {
+    Enabled,
+    Disabled,
+    Toggle,
+    Corrupted,
+}
+
+impl RegionKind {
+    fn style(&self) -> Style {
+        match self {
+            RegionKind::Enabled => Style::new().green().bold(),
+            RegionKind::Disabled => Style::new().red(),
+            RegionKind::Toggle => Style::new().blue().bold(),
+            RegionKind::Corrupted => Style::new().bright_black(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Region {
+    kind: RegionKind,
+    start: usize,
+    end: usize,
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct MemoryDump {
+    part01: u64,
+    part02: u64,
+    regions: Vec<Region>,
+}
+
+impl MemoryDump {
+    fn new(memory: &[u8], steps: &[Step]) -> Self {
+        let mut totals = Totals::default();
+        let mut regions = Vec::new();
+        let mut cursor = 0;
+
+        let region = |kind, span: Range<usize>, value| Region {
+            kind,
+            start: span.start,
+            end: span.end,
+            text: String::from_utf8_lossy(&memory[span]).into_owned(),
+            value,
+        };
+
+        for step in steps {
+            let span = step.token.span.clone();
+
+            if cursor < span.start {
+                regions.push(region(RegionKind::Corrupted, cursor..span.start, None));
+            }
+
+            cursor = span.end;
+            totals.add(step);
+
+            regions.push(match step.effect {
+                Effect::Value(value) if step.enabled => region(RegionKind::Enabled, span, Some(value)),
+                Effect::Value(value) => region(RegionKind::Disabled, span, Some(value)),
+                Effect::Control => region(RegionKind::Toggle, span, None),
+            });
+        }
+
+        if cursor < memory.len() {
+            regions.push(region(RegionKind::Corrupted, cursor..memory.len(), None));
+        }
+
+        MemoryDump {
+            part01: totals.part01,
+            part02: totals.part02,
+            regions,
+        }
+    }
+
+    fn print(&self) {
+        for region in &self.regions {
+            print!("{}", region.text.style(region.kind.style()));
+        }
+
+        println!();
+        println!(
+            "{} {} {} {}",
+            "enabled mul".style(RegionKind::Enabled.style()),
+            "disabled mul".style(RegionKind::Disabled.style()),
+            "do/don't".style(RegionKind::Toggle.style()),
+            "corrupted".style(RegionKind::Corrupted.style()),
+        );
+    }
+}
+
+/////////////////////////////////////////////////////////////////////
+// main
+/////////////////////////////////////////////////////////////////////
+fn main() {
+    let trace = std::env::args().any(|arg| arg == "--trace");
+    let annotate = std::env::args().any(|arg| arg == "--annotate");
+    let json = std::env::args().any(|arg| arg == "--json");
+
+    let mut instructions = InstructionSet::default();
+    instructions.register(Mul);
+    instructions.register(Do);
+    instructions.register(Dont);
+
+    if std::env::args().any(|arg| arg == "--add") {
+        instructions.register(Add);
+    }
+
+    let mut machine = Machine::new(&instructions);
+
+    if annotate || json {
+        let memory = std::fs::read("day-03/input.txt").expect("error opening file day-03/input.txt");
+        let steps = instructions.tokenize(&memory[..])
+            .filter_map(|token| machine.execute(token))
+            .collect::<Vec<_>>();
+
+        let dump = MemoryDump::new(&memory, &steps);
+
+        if json {
+            println!("{}", serde_json::to_string_pretty(&dump).unwrap());
+        } else {
+            dump.print();
+            println!("part 01: {}", dump.part01);
+            println!("part 02: {}", dump.part02);
+        }
+
+        return;
+    }
+
+    let file = std::fs::File::open("day-03/input.txt").expect("error opening file day-03/input.txt");
+    let mut totals = Totals::default();
+
+    for token in instructions.tokenize(file) {
+        if let Some(step) = machine.execute(token) {
+            if trace {
+                println!("{step}");
+            }
+
+            totals.add(&step);
+        }
     }
 
-    println!("part 01: {part01}");
-    println!("part 02: {part02}");
+    println!("part 01: {}", totals.part01);
+    println!("part 02: {}", totals.part02);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn puzzle_instructions() -> InstructionSet {
+        let mut instructions = InstructionSet::default();
+        instructions.register(Mul);
+        instructions.register(Do);
+        instructions.register(Dont);
+
+        instructions
+    }
+
+    fn run(instructions: &InstructionSet, memory: &[u8]) -> (u64, u64) {
+        let mut machine = Machine::new(instructions);
+        let mut totals = Totals::default();
+
+        for step in instructions.tokenize(memory).filter_map(|token| machine.execute(token)) {
+            totals.add(&step);
+        }
+
+        (totals.part01, totals.part02)
+    }
+
+    #[test]
+    fn example_part_1() {
+        let memory = b"xmul(2,4)%&mul[3,7]!@^do_not_mul(5,5)+mul(32,64]then(mul(11,8)mul(8,5))";
+        assert_eq!(run(&puzzle_instructions(), memory).0, 161);
+    }
+
+    #[test]
+    fn example_part_2() {
+        let memory = b"xmul(2,4)&mul[3,7]!^don't()_mul(5,5)+mul(32,64](mul(11,8)undo()?mul(8,5))";
+        assert_eq!(run(&puzzle_instructions(), memory).1, 48);
+    }
+
+    #[test]
+    fn registered_add() {
+        let mut instructions = puzzle_instructions();
+        instructions.register(Add);
+
+        let memory = b"add(2,3)mul(2,3)add(1000,1)add(999,1)don't()add(4,4)";
+        assert_eq!(run(&instructions, memory), (5 + 6 + 1000 + 8, 5 + 6 + 1000));
+        assert_eq!(run(&puzzle_instructions(), memory), (6, 6));
+    }
+
+    #[test]
+    fn instruction_across_lines() {
+        let memory = b"mu\nl(12,\r\n3)don'\nt()mul(2,2)";
+        assert_eq!(run(&puzzle_instructions(), memory), (40, 36));
+    }
+
+    #[test]
+    fn restart_inside_name() {
+        let tokens = puzzle_instructions().tokenize(&b"mumul(1,2)dodon't()mul(1,mul(3,4)"[..])
+            .map(|token| (token.to_string(), token.span))
+            .collect::<Vec<_>>();
+
+        assert_eq!(tokens, vec![
+            ("mul(1,2)".to_string(), 2..10),
+            ("don't()".to_string(), 12..19),
+            ("mul(3,4)".to_string(), 25..33),
+        ]);
+    }
+
+    #[test]
+    fn dump_regions() {
+        let instructions = puzzle_instructions();
+        let mut machine = Machine::new(&instructions);
+
+        let memory = b"xmul(2,4)don't()mul(1000,1)mul(3,3)";
+        let steps = instructions.tokenize(&memory[..])
+            .filter_map(|token| machine.execute(token))
+            .collect::<Vec<_>>();
+
+        let dump = MemoryDump::new(memory, &steps);
+        let regions = dump.regions.iter()
+            .map(|region| (region.kind, region.start, region.end))
+            .collect::<Vec<_>>();
+
+        assert_eq!(regions, vec![
+            (RegionKind::Corrupted, 0, 1),
+            (RegionKind::Enabled, 1, 9),
+            (RegionKind::Toggle, 9, 16),
+            (RegionKind::Corrupted, 16, 27),
+            (RegionKind::Disabled, 27, 35),
+        ]);
+        assert_eq!((dump.part01, dump.part02), (17, 8));
+    }
 }
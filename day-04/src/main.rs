@@ -1,5 +1,6 @@
 extern crate nalgebra as na;
 
+use aho_corasick::AhoCorasick;
 use na::{point, vector, Point2, Vector2};
 
 macro_rules! read_lines {
@@ -12,71 +13,392 @@ macro_rules! read_lines {
     };
 }
 
+const DIRECTIONS: [(&str, Vector2<i32>); 8] = [
+    ("E", vector![1, 0]),
+    ("SE", vector![1, 1]),
+    ("S", vector![0, 1]),
+    ("SW", vector![-1, 1]),
+    ("W", vector![-1, 0]),
+    ("NW", vector![-1, -1]),
+    ("N", vector![0, -1]),
+    ("NE", vector![1, -1]),
+];
+
+fn direction_name(direction: &Vector2<i32>) -> &'static str {
+    DIRECTIONS.iter()
+        .find(|(_, dir)| dir == direction)
+        .map(|(name, _)| *name)
+        .unwrap()
+}
+
+fn parse_direction(name: &str) -> Vector2<i32> {
+    DIRECTIONS.iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, dir)| *dir)
+        .unwrap_or_else(|| panic!("unknown direction {name}"))
+}
+
 fn get_letter<'a>(grid: &'a [Vec<char>], coord: &Point2<i32>) -> Option<&'a char> {
     grid.get(coord.y as usize)
         .and_then(|row| row.get(coord.x as usize))
 }
 
-fn extract_word(grid: &[Vec<char>], coord: &Point2<i32>, direction: &Vector2<i32>) -> String {
-    (0..3)
-        .map(move |i| coord + (i + 1) * direction)
-        .filter_map(|coord| get_letter(grid, &coord))
-        .copied()
-        .collect()
+/////////////////////////////////////////////////////////////////////
+// Word Search
+/////////////////////////////////////////////////////////////////////
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct WordMatch {
+    word: String,
+    start: Point2<i32>,
+    direction: Vector2<i32>,
 }
 
-fn main() {
-    let directions = [
-      vector![1, 0],
-      vector![1, 1],
-      vector![0, 1],
-      vector![-1, 1],
-      vector![-1, 0],
-      vector![-1, -1],
-      vector![0, -1],
-      vector![1, -1]
-    ];
-    let x_moves = [
-      vector![1, 1],
-      vector![-1, 1],
-      vector![-1, -1],
-      vector![1, -1]
-    ];
+struct WordSearch<'g> {
+    grid: &'g [Vec<char>],
+    directions: Vec<Vector2<i32>>,
+    wrap: bool,
+}
+
+impl<'g> WordSearch<'g> {
+    fn new(grid: &'g [Vec<char>]) -> Self {
+        WordSearch {
+            grid,
+            directions: DIRECTIONS.iter().map(|(_, dir)| *dir).collect(),
+            wrap: false,
+        }
+    }
+
+    fn with_directions(mut self, directions: Vec<Vector2<i32>>) -> Self {
+        self.directions = directions;
+        self
+    }
+
+    fn with_wrap(mut self, wrap: bool) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    fn letter(&self, coord: &Point2<i32>) -> Option<&char> {
+        if self.wrap {
+            let row = self.grid.get(coord.y.rem_euclid(self.grid.len() as i32) as usize)?;
+            row.get(coord.x.rem_euclid(row.len() as i32) as usize)
+        } else {
+            get_letter(self.grid, coord)
+        }
+    }
+
+    fn matches(&self, word: &str, start: &Point2<i32>, direction: &Vector2<i32>) -> bool {
+        word.chars()
+            .enumerate()
+            .all(|(i, letter)| self.letter(&(start + i as i32 * direction)) == Some(&letter))
+    }
+
+    fn find(&self, words: &[&str]) -> Vec<WordMatch> {
+        let mut result = Vec::new();
+
+        for (y, row) in self.grid.iter().enumerate() {
+            for (x, letter) in row.iter().enumerate() {
+                let start = point![x as i32, y as i32];
+
+                for word in words.iter().filter(|word| word.starts_with(*letter)) {
+                    for direction in &self.directions {
+                        if self.matches(word, &start, direction) {
+                            result.push(WordMatch {
+                                word: word.to_string(),
+                                start,
+                                direction: *direction,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        result
+    }
+
+    // Lines of the grid read along the given direction, with the coordinates of each byte
+    fn lines(&self, direction: &Vector2<i32>) -> Vec<(String, Vec<Point2<i32>>)> {
+        let mut lines = Vec::new();
+
+        for (y, row) in self.grid.iter().enumerate() {
+            for x in 0..row.len() {
+                let mut point = point![x as i32, y as i32];
+
+                if get_letter(self.grid, &(point - direction)).is_some() {
+                    continue;
+                }
+
+                let mut line = String::new();
+                let mut points = Vec::new();
+
+                while let Some(letter) = get_letter(self.grid, &point) {
+                    line.push(*letter);
+                    points.resize(line.len(), point);
+                    point += direction;
+                }
+
+                lines.push((line, points));
+            }
+        }
+
+        lines
+    }
+
+    // Same matches as find, but all words are searched at once along each grid line
+    fn find_all(&self, words: &[&str]) -> Vec<WordMatch> {
+        // Wrapped lines have no start or end to scan from, so fall back to walking from each cell
+        if self.wrap {
+            return self.find(words);
+        }
+
+        // Empty words never match when walking, skip them here as well
+        let words = words.iter().copied().filter(|word| !word.is_empty()).collect::<Vec<_>>();
+        let automaton = AhoCorasick::new(&words).unwrap();
+        let mut result = Vec::new();
+
+        for direction in &self.directions {
+            for (line, points) in self.lines(direction) {
+                for m in automaton.find_overlapping_iter(&line) {
+                    result.push(WordMatch {
+                        word: words[m.pattern()].to_string(),
+                        start: points[m.start()],
+                        direction: *direction,
+                    });
+                }
+            }
+        }
+
+        result
+    }
+}
+
+/////////////////////////////////////////////////////////////////////
+// Template
+/////////////////////////////////////////////////////////////////////
+const X_MAS: &str = "M.S/.A./M.S";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Template {
+    cells: Vec<Vec<Option<char>>>,
+}
+
+impl Template {
+    // Rows are separated by '/' or new lines, '.' matches any letter
+    fn parse(pattern: &str) -> Self {
+        Template {
+            cells: pattern.split(['/', '\n'])
+                .map(|row| row.chars().map(|c| if c == '.' { None } else { Some(c) }).collect())
+                .collect(),
+        }
+    }
+
+    fn width(&self) -> usize {
+        self.cells.iter().map(Vec::len).max().unwrap_or(0)
+    }
+
+    fn height(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn cell(&self, x: usize, y: usize) -> Option<char> {
+        self.cells.get(y).and_then(|row| row.get(x)).copied().flatten()
+    }
+
+    fn rotate(&self) -> Template {
+        let height = self.height();
+
+        Template {
+            cells: (0..self.width())
+                .map(|x| (0..height).map(|y| self.cell(x, height - 1 - y)).collect())
+                .collect(),
+        }
+    }
+
+    fn reflect(&self) -> Template {
+        let width = self.width();
+
+        Template {
+            cells: (0..self.height())
+                .map(|y| (0..width).map(|x| self.cell(width - 1 - x, y)).collect())
+                .collect(),
+        }
+    }
+
+    // All distinct rotations and reflections of the template
+    fn variants(&self) -> Vec<Template> {
+        let mut variants: Vec<Template> = Vec::new();
+        let mut current = self.clone();
+
+        for _ in 0..4 {
+            for variant in [current.reflect(), current.clone()] {
+                if !variants.contains(&variant) {
+                    variants.push(variant);
+                }
+            }
+
+            current = current.rotate();
+        }
+
+        variants
+    }
+
+    fn matches_at(&self, grid: &[Vec<char>], origin: &Point2<i32>) -> bool {
+        self.cells.iter().enumerate().all(|(y, row)| {
+            row.iter().enumerate().all(|(x, cell)| {
+                let letter = get_letter(grid, &(origin + vector![x as i32, y as i32]));
+
+                match cell {
+                    Some(expected) => letter == Some(expected),
+                    None => letter.is_some(),
+                }
+            })
+        })
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct TemplateMatch {
+    origin: Point2<i32>,
+    variant: Template,
+}
+
+fn find_template(grid: &[Vec<char>], template: &Template) -> Vec<TemplateMatch> {
+    let mut result = Vec::new();
+
+    for variant in template.variants() {
+        for (y, row) in grid.iter().enumerate() {
+            for x in 0..row.len() {
+                let origin = point![x as i32, y as i32];
+
+                if variant.matches_at(grid, &origin) {
+                    result.push(TemplateMatch { origin, variant: variant.clone() });
+                }
+            }
+        }
+    }
 
+    result
+}
+
+/////////////////////////////////////////////////////////////////////
+// main
+/////////////////////////////////////////////////////////////////////
+fn main() {
     let grid = read_lines!("day-04/input.txt")
         .map(|line| line.chars().collect::<Vec<_>>())
         .collect::<Vec<_>>();
 
-    let mut part01 = 0;
-    let mut part02 = 0;
+    // Custom search
+    let words = std::env::args().find_map(|arg| arg.strip_prefix("--words=").map(str::to_string));
 
-    for (y, row) in grid.iter().enumerate() {
-        for (x, _) in row.iter().enumerate().filter(|(_, &letter)| letter == 'X') {
-            let pt = point![x as i32, y as i32];
+    if let Some(words) = words {
+        let mut search = WordSearch::new(&grid)
+            .with_wrap(std::env::args().any(|arg| arg == "--wrap"));
 
-            part01 += directions.iter()
-                .map(|dir| extract_word(&grid, &pt, dir))
-                .filter(|word| *word == "MAS")
-                .count();
+        if let Some(directions) = std::env::args().find_map(|arg| arg.strip_prefix("--directions=").map(str::to_string)) {
+            search = search.with_directions(directions.split(',').map(parse_direction).collect());
         }
 
-        for (x, _) in row.iter().enumerate().filter(|(_, &letter)| letter == 'A') {
-            let pt = point![x as i32, y as i32];
+        let matches = search.find_all(&words.split(',').collect::<Vec<_>>());
 
-            let x_word = x_moves.iter()
-                .filter_map(|dir| get_letter(&grid, &(pt + dir)))
-                .copied()
-                .collect::<String>();
+        for m in &matches {
+            println!("{} at ({},{}) going {}", m.word, m.start.x, m.start.y, direction_name(&m.direction));
+        }
 
-            match x_word.as_str() {
-                "MMSS" | "SMMS" | "MSSM" | "SSMM" => {
-                    part02 += 1;
-                }
-                _ => {}
-            }
+        println!("found {} matches", matches.len());
+        return;
+    }
+
+    if let Some(pattern) = std::env::args().find_map(|arg| arg.strip_prefix("--template=").map(str::to_string)) {
+        let matches = find_template(&grid, &Template::parse(&pattern));
+
+        for m in &matches {
+            let variant = m.variant.cells.iter()
+                .map(|row| row.iter().map(|c| c.unwrap_or('.')).collect::<String>())
+                .collect::<Vec<_>>();
+
+            println!("({},{}) {}", m.origin.x, m.origin.y, variant.join("/"));
         }
+
+        println!("found {} matches", matches.len());
+        return;
     }
 
+    // Part 1
+    let part01 = WordSearch::new(&grid).find_all(&["XMAS"]).len();
+
+    // Part 2
+    let part02 = find_template(&grid, &Template::parse(X_MAS)).len();
+
     println!("part 01: {}", part01);
     println!("part 02: {}", part02);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "MMMSXXMASM
+MSAMXMSMSA
+AMXSXMAAMM
+MSAMASMSMX
+XMASAMXAMM
+XXAMMXXAMA
+SMSMSASXSS
+SAXAMASAAA
+MAMMMXMMMM
+MXMXAXMASX";
+
+    fn example() -> Vec<Vec<char>> {
+        EXAMPLE.lines().map(|line| line.chars().collect()).collect()
+    }
+
+    #[test]
+    fn example_xmas() {
+        assert_eq!(WordSearch::new(&example()).find(&["XMAS"]).len(), 18);
+    }
+
+    #[test]
+    fn selected_directions_and_wrap() {
+        let grid = vec!["ASXM".chars().collect::<Vec<_>>()];
+
+        let search = WordSearch::new(&grid).with_directions(vec![parse_direction("E")]);
+        assert_eq!(search.find(&["XMAS"]), vec![]);
+
+        let search = search.with_wrap(true);
+        assert_eq!(search.find(&["XMAS"]), vec![
+            WordMatch { word: "XMAS".to_string(), start: point![2, 0], direction: vector![1, 0] },
+        ]);
+    }
+
+    #[test]
+    fn automaton_matches_walking() {
+        let grid = example();
+        let words = ["XMAS", "MAS", "", "AM", "SAMX", "MMMSXX"];
+
+        for search in [WordSearch::new(&grid), WordSearch::new(&grid).with_directions(vec![vector![-1, 1], vector![0, -1]])] {
+            let sort_key = |m: &WordMatch| (m.word.clone(), m.start.x, m.start.y, m.direction.x, m.direction.y);
+
+            let mut walking = search.find(&words);
+            walking.sort_by_key(sort_key);
+
+            let mut automaton = search.find_all(&words);
+            automaton.sort_by_key(sort_key);
+
+            assert_eq!(walking, automaton);
+        }
+    }
+
+    #[test]
+    fn example_x_mas() {
+        assert_eq!(find_template(&example(), &Template::parse(X_MAS)).len(), 9);
+    }
+
+    #[test]
+    fn template_variants() {
+        assert_eq!(Template::parse(X_MAS).variants().len(), 4);
+        assert_eq!(Template::parse(".M./MAM/.M.").variants().len(), 1);
+        assert_eq!(Template::parse("XM/.A").variants().len(), 8);
+    }
+}
@@ -1,5 +1,6 @@
+use serde::{Serialize, Serializer};
 use std::collections::{HashMap, HashSet};
-use std::mem::swap;
+use std::fmt::{Display, Formatter};
 
 macro_rules! read_lines {
     ($file:literal) => {
@@ -11,32 +12,350 @@ macro_rules! read_lines {
     };
 }
 
-fn check_update(update: &[i32], rules: &HashMap<i32, HashSet<i32>>) -> Option<(usize, usize)> {
-    let mut previous = HashSet::new();
+fn violations(update: &[i32], rules: &HashMap<i32, HashSet<i32>>) -> Vec<(i32, i32)> {
+    let mut result = Vec::new();
 
     for (idx, page) in update.iter().enumerate() {
         if let Some(next) = rules.get(page) {
-            let common = previous.intersection(next)
-                .map(|p| update[..idx].iter().position(|r| r == p).unwrap())
-                .min();
+            for previous in update[..idx].iter().filter(|p| next.contains(p)) {
+                result.push((*page, *previous));
+            }
+        }
+    }
+
+    result
+}
+
+#[derive(Debug, Eq, PartialEq)]
+struct Cycle {
+    pages: Vec<i32>,
+}
+
+impl Display for Cycle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for page in &self.pages {
+            write!(f, "{page} -> ")?;
+        }
+
+        write!(f, "{}", self.pages[0])
+    }
+}
+
+fn sort_update(update: &[i32], rules: &HashMap<i32, HashSet<i32>>) -> Result<Vec<i32>, Cycle> {
+    let pages = update.iter().copied().collect::<HashSet<_>>();
+    let mut in_degrees = update.iter().map(|&page| (page, 0)).collect::<HashMap<_, _>>();
+
+    for page in &pages {
+        for next in rules.get(page).into_iter().flatten().filter(|next| pages.contains(next)) {
+            *in_degrees.get_mut(next).unwrap() += 1;
+        }
+    }
+
+    // Kahn's algorithm, ties are broken using the update order so valid updates are left untouched
+    let mut remaining = update.to_vec();
+    let mut sorted = Vec::with_capacity(update.len());
+
+    while !remaining.is_empty() {
+        let Some(idx) = remaining.iter().position(|page| in_degrees[page] == 0) else {
+            return Err(find_cycle(&remaining, rules));
+        };
+
+        let page = remaining.remove(idx);
+
+        for next in rules.get(&page).into_iter().flatten() {
+            if let Some(degree) = in_degrees.get_mut(next) {
+                *degree -= 1;
+            }
+        }
+
+        sorted.push(page);
+    }
+
+    Ok(sorted)
+}
+
+fn find_cycle(remaining: &[i32], rules: &HashMap<i32, HashSet<i32>>) -> Cycle {
+    // Every remaining page has a remaining predecessor, walking back must loop
+    let mut path = vec![remaining[0]];
+
+    loop {
+        let current = path.last().unwrap();
+        let previous = *remaining.iter()
+            .find(|page| rules.get(page).is_some_and(|next| next.contains(current)))
+            .unwrap();
+
+        if let Some(idx) = path.iter().position(|&page| page == previous) {
+            let mut pages = path[idx..].to_vec();
+            pages.reverse();
+
+            return Cycle { pages };
+        }
+
+        path.push(previous);
+    }
+}
+
+// Pages outside the longest subsequence already in corrected order.
+// Minimal as long as rules totally order the update, which puzzle inputs do.
+fn pages_to_move(update: &[i32], sorted: &[i32]) -> Vec<i32> {
+    let ranks = update.iter()
+        .map(|page| sorted.iter().position(|p| p == page).unwrap())
+        .collect::<Vec<_>>();
+
+    let mut lengths = vec![1; ranks.len()];
+    let mut parents = vec![None; ranks.len()];
+
+    for j in 0..ranks.len() {
+        for i in 0..j {
+            if ranks[i] < ranks[j] && lengths[i] + 1 > lengths[j] {
+                lengths[j] = lengths[i] + 1;
+                parents[j] = Some(i);
+            }
+        }
+    }
+
+    let mut kept = HashSet::new();
+    let mut current = (0..ranks.len()).max_by_key(|&idx| lengths[idx]);
+
+    while let Some(idx) = current {
+        kept.insert(idx);
+        current = parents[idx];
+    }
+
+    update.iter().enumerate()
+        .filter(|(idx, _)| !kept.contains(idx))
+        .map(|(_, &page)| page)
+        .collect()
+}
+
+/////////////////////////////////////////////////////////////////////
+// Analysis
+/////////////////////////////////////////////////////////////////////
+fn reachable(from: i32, rules: &HashMap<i32, HashSet<i32>>, pages: &HashSet<i32>, skip: Option<(i32, i32)>) -> HashSet<i32> {
+    let mut reached = HashSet::new();
+    let mut stack = vec![from];
+
+    while let Some(page) = stack.pop() {
+        for &next in rules.get(&page).into_iter().flatten() {
+            if !pages.contains(&next) || skip == Some((page, next)) {
+                continue;
+            }
+
+            if reached.insert(next) {
+                stack.push(next);
+            }
+        }
+    }
+
+    reached
+}
+
+fn closure(rules: &HashMap<i32, HashSet<i32>>, pages: &HashSet<i32>) -> HashMap<i32, HashSet<i32>> {
+    pages.iter()
+        .map(|&page| (page, reachable(page, rules, pages, None)))
+        .collect()
+}
+
+fn unordered_pairs(closure: &HashMap<i32, HashSet<i32>>) -> Vec<(i32, i32)> {
+    let mut pages = closure.keys().copied().collect::<Vec<_>>();
+    pages.sort();
+
+    let mut result = Vec::new();
+
+    for (idx, a) in pages.iter().enumerate() {
+        for b in &pages[idx + 1..] {
+            if !closure[a].contains(b) && !closure[b].contains(a) {
+                result.push((*a, *b));
+            }
+        }
+    }
+
+    result
+}
+
+#[derive(Debug)]
+struct RuleAnalysis {
+    pages: HashSet<i32>,
+    closure: HashMap<i32, HashSet<i32>>,
+    redundant: Vec<(i32, i32)>,
+    unordered: Vec<(i32, i32)>,
+    cyclic: Vec<i32>,
+}
+
+impl RuleAnalysis {
+    fn new(rules: &HashMap<i32, HashSet<i32>>) -> Self {
+        let pages = rules.iter()
+            .flat_map(|(x, next)| next.iter().chain([x]))
+            .copied()
+            .collect::<HashSet<_>>();
+
+        let closure = closure(rules, &pages);
+
+        // A rule is redundant if its pages are still ordered without it
+        let mut redundant = rules.iter()
+            .flat_map(|(&x, next)| next.iter().map(move |&y| (x, y)))
+            .filter(|&(x, y)| reachable(x, rules, &pages, Some((x, y))).contains(&y))
+            .collect::<Vec<_>>();
+
+        redundant.sort();
+
+        let mut cyclic = pages.iter()
+            .filter(|page| closure[page].contains(page))
+            .copied()
+            .collect::<Vec<_>>();
+
+        cyclic.sort();
+
+        RuleAnalysis {
+            unordered: unordered_pairs(&closure),
+            pages,
+            closure,
+            redundant,
+            cyclic,
+        }
+    }
 
-            if let Some(index) = common {
-                return Some((idx, index)); // <= returns index pair to invert
+    fn rule_count(&self, rules: &HashMap<i32, HashSet<i32>>) -> usize {
+        rules.values().map(HashSet::len).sum()
+    }
+
+    fn print(&self, rules: &HashMap<i32, HashSet<i32>>, updates: &[Vec<i32>]) {
+        let pairs = |pairs: &[(i32, i32)], sep: &str| pairs.iter()
+            .map(|(a, b)| format!("{a}{sep}{b}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        println!("rules: {} on {} pages", self.rule_count(rules), self.pages.len());
+        println!("closure: {} ordered pairs", self.closure.values().map(HashSet::len).sum::<usize>());
+        println!("cyclic pages ({}): {}", self.cyclic.len(), join(&self.cyclic));
+        println!("redundant rules ({}): {}", self.redundant.len(), pairs(&self.redundant, "|"));
+        println!("unordered pairs ({}): {}", self.unordered.len(), pairs(&self.unordered, "/"));
+
+        let mut total = 0;
+
+        for update in updates {
+            let pages = update.iter().copied().collect::<HashSet<_>>();
+            let unordered = unordered_pairs(&closure(rules, &pages));
+
+            match sort_update(update, rules) {
+                Ok(_) if unordered.is_empty() => total += 1,
+                Ok(_) => println!("{}: not total, unordered {}", join(update), pairs(&unordered, "/")),
+                Err(cycle) => println!("{}: not total, cyclic {cycle}", join(update)),
+            }
+        }
+
+        println!("totally ordered updates: {total}/{}", updates.len());
+    }
+
+    fn to_dot(&self, rules: &HashMap<i32, HashSet<i32>>) -> String {
+        let mut dot = String::from("digraph rules {\n");
+        let mut edges = rules.iter()
+            .flat_map(|(&x, next)| next.iter().map(move |&y| (x, y)))
+            .collect::<Vec<_>>();
+
+        edges.sort();
+
+        for edge in edges {
+            if self.redundant.binary_search(&edge).is_ok() {
+                dot += &format!("  {} -> {} [style=dashed];\n", edge.0, edge.1);
+            } else {
+                dot += &format!("  {} -> {};\n", edge.0, edge.1);
+            }
+        }
+
+        dot += "}";
+        dot
+    }
+}
+
+/////////////////////////////////////////////////////////////////////
+// Report
+/////////////////////////////////////////////////////////////////////
+#[derive(Debug, Serialize)]
+struct UpdateReport {
+    update: Vec<i32>,
+    #[serde(serialize_with = "serialize_rules")]
+    violations: Vec<(i32, i32)>,
+    to_move: Vec<i32>,
+    corrected: Option<Vec<i32>>,
+    cycle: Option<Vec<i32>>,
+}
+
+fn serialize_rules<S: Serializer>(rules: &[(i32, i32)], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(rules.iter().map(|(x, y)| format!("{x}|{y}")))
+}
+
+fn join(pages: &[i32]) -> String {
+    pages.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(",")
+}
+
+impl UpdateReport {
+    fn new(update: Vec<i32>, rules: &HashMap<i32, HashSet<i32>>) -> Self {
+        let violations = violations(&update, rules);
+        let mut report = UpdateReport { update, violations, to_move: Vec::new(), corrected: None, cycle: None };
+
+        if report.is_correct() {
+            return report;
+        }
+
+        match sort_update(&report.update, rules) {
+            Ok(sorted) => {
+                report.to_move = pages_to_move(&report.update, &sorted);
+                report.corrected = Some(sorted);
             }
+            Err(cycle) => report.cycle = Some(cycle.pages),
         }
 
-        previous.insert(*page);
+        report
     }
 
-    None
+    fn is_correct(&self) -> bool {
+        self.violations.is_empty()
+    }
 }
 
+impl Display for UpdateReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_correct() {
+            return write!(f, "{}: ok", join(&self.update));
+        }
+
+        writeln!(f, "{}: {} violation(s)", join(&self.update), self.violations.len())?;
+
+        for (x, y) in &self.violations {
+            writeln!(f, "  violates {x}|{y}")?;
+        }
+
+        if let Some(pages) = &self.cycle {
+            return write!(f, "  cannot reorder, rules are cyclic: {}", Cycle { pages: pages.clone() });
+        }
+
+        writeln!(f, "  move {}", join(&self.to_move))?;
+        write!(f, "  corrected: {}", join(self.corrected.as_ref().unwrap()))
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Summary<'r> {
+    part01: i32,
+    part02: i32,
+    updates: &'r [UpdateReport],
+}
+
+/////////////////////////////////////////////////////////////////////
+// main
+/////////////////////////////////////////////////////////////////////
 fn main() {
+    let explain = std::env::args().any(|arg| arg == "--explain");
+    let json = std::env::args().any(|arg| arg == "--json");
+    let analyze = std::env::args().any(|arg| arg == "--analyze");
+    let dot = std::env::args().any(|arg| arg == "--dot");
+
     let mut rules: HashMap<i32, HashSet<i32>> = HashMap::new();
     let mut rules_loaded = false;
+    let mut reports = Vec::new();
     let mut part01 = 0;
     let mut part02 = 0;
-    let mut moves = 0;
 
     for line in read_lines!("day-05/input.txt") {
         if line.is_empty() {
@@ -57,30 +376,105 @@ fn main() {
                 .insert(y);
         } else {
             // Parse updates
-            let mut update: Vec<i32> = line.split(',').map(|p| p.parse().unwrap()).collect();
-            let mut correct = true;
+            let update: Vec<i32> = line.split(',').map(|p| p.parse().unwrap()).collect();
+            let report = UpdateReport::new(update, &rules);
 
-            while let Some((to_move_idx, before_idx)) = check_update(&update, &rules) {
-                assert!(to_move_idx > before_idx);
-                correct = false;
+            if report.is_correct() {
+                part01 += report.update[report.update.len() / 2];
+            } else if let Some(sorted) = &report.corrected {
+                part02 += sorted[sorted.len() / 2];
+            } else if !explain && !json && !analyze && !dot {
+                println!("{report}");
+            }
 
-                let mut tmp = update[to_move_idx];
+            if explain {
+                println!("{report}");
+            }
 
-                for page in &mut update[before_idx..=to_move_idx] {
-                    swap(page, &mut tmp);
-                }
+            reports.push(report);
+        }
+    }
 
-                moves += 1;
-            }
+    if analyze || dot {
+        let analysis = RuleAnalysis::new(&rules);
 
-            if correct {
-                part01 += update[update.len() / 2];
-            } else {
-                part02 += update[update.len() / 2];
-            }
+        if dot {
+            println!("{}", analysis.to_dot(&rules));
+        } else {
+            let updates = reports.into_iter().map(|r| r.update).collect::<Vec<_>>();
+            analysis.print(&rules, &updates);
         }
+
+        return;
+    }
+
+    if json {
+        let summary = Summary { part01, part02, updates: &reports };
+
+        println!("{}", serde_json::to_string_pretty(&summary).unwrap());
+        return;
     }
 
     println!("part 01: {part01}");
-    println!("part 02: {part02} ({moves})");
+    println!("part 02: {part02}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(pairs: &[(i32, i32)]) -> HashMap<i32, HashSet<i32>> {
+        let mut rules: HashMap<i32, HashSet<i32>> = HashMap::new();
+
+        for &(x, y) in pairs {
+            rules.entry(x).or_default().insert(y);
+        }
+
+        rules
+    }
+
+    const EXAMPLE_RULES: [(i32, i32); 21] = [
+        (47, 53), (97, 13), (97, 61), (97, 47), (75, 29), (61, 13), (75, 53), (29, 13),
+        (97, 29), (53, 29), (61, 53), (97, 53), (61, 29), (47, 13), (75, 47), (97, 75),
+        (47, 61), (75, 61), (47, 29), (75, 13), (53, 13),
+    ];
+
+    #[test]
+    fn sort_example_updates() {
+        let rules = rules(&EXAMPLE_RULES);
+
+        assert_eq!(sort_update(&[75, 47, 61, 53, 29], &rules), Ok(vec![75, 47, 61, 53, 29]));
+        assert_eq!(sort_update(&[75, 97, 47, 61, 53], &rules), Ok(vec![97, 75, 47, 61, 53]));
+        assert_eq!(sort_update(&[61, 13, 29], &rules), Ok(vec![61, 29, 13]));
+        assert_eq!(sort_update(&[97, 13, 75, 29, 47], &rules), Ok(vec![97, 75, 47, 29, 13]));
+    }
+
+    #[test]
+    fn explain_update() {
+        let rules = rules(&EXAMPLE_RULES);
+        let report = UpdateReport::new(vec![97, 13, 75, 29, 47], &rules);
+
+        assert_eq!(report.violations, vec![(75, 13), (29, 13), (47, 13), (47, 29)]);
+        assert_eq!(report.to_move, vec![13, 29]);
+        assert_eq!(report.corrected, Some(vec![97, 75, 47, 29, 13]));
+    }
+
+    #[test]
+    fn analyze_rules() {
+        let rules = rules(&[(1, 2), (2, 3), (1, 3), (3, 4), (5, 4)]);
+        let analysis = RuleAnalysis::new(&rules);
+
+        assert_eq!(analysis.closure[&1], HashSet::from([2, 3, 4]));
+        assert_eq!(analysis.redundant, vec![(1, 3)]);
+        assert_eq!(analysis.unordered, vec![(1, 5), (2, 5), (3, 5)]);
+        assert!(analysis.cyclic.is_empty());
+    }
+
+    #[test]
+    fn detect_cycle() {
+        let rules = rules(&[(1, 2), (2, 3), (3, 1), (4, 1)]);
+
+        assert_eq!(sort_update(&[4, 3, 2, 1], &rules), Err(Cycle { pages: vec![1, 2, 3] }));
+        assert_eq!(sort_update(&[3, 2, 4], &rules), Ok(vec![2, 3, 4]));
+    }
 }
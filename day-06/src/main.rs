@@ -1,6 +1,9 @@
 use std::collections::{HashMap, HashSet};
 use std::iter::FusedIterator;
+use std::time::Instant;
 use nalgebra::{point, vector, Point2, Vector2};
+use owo_colors::{OwoColorize, Style};
+use rayon::prelude::*;
 
 macro_rules! read_lines {
     ($file:literal) => {
@@ -31,6 +34,156 @@ fn turn_right(dir: Vector2<i32>) -> Vector2<i32> {
     }
 }
 
+fn parse_direction(c: char) -> Option<Vector2<i32>> {
+    match c {
+        '^' => Some(UP),
+        '>' => Some(RIGHT),
+        'v' => Some(DOWN),
+        '<' => Some(LEFT),
+        _ => None,
+    }
+}
+
+fn direction_index(dir: &Vector2<i32>) -> usize {
+    if dir == &UP {
+        0
+    } else if dir == &RIGHT {
+        1
+    } else if dir == &DOWN {
+        2
+    } else if dir == &LEFT {
+        3
+    } else {
+        unreachable!()
+    }
+}
+
+/////////////////////////////////////////////////////////////////////
+// GuardRule
+/////////////////////////////////////////////////////////////////////
+// Rules only depend on guard state, so a repeated state still means a loop
+trait GuardRule: Sync {
+    fn turn(&self, position: &Point2<i32>, dir: Vector2<i32>) -> Vector2<i32>;
+}
+
+struct TurnRight;
+
+impl GuardRule for TurnRight {
+    fn turn(&self, _position: &Point2<i32>, dir: Vector2<i32>) -> Vector2<i32> {
+        turn_right(dir)
+    }
+}
+
+struct TurnLeft;
+
+impl GuardRule for TurnLeft {
+    fn turn(&self, _position: &Point2<i32>, dir: Vector2<i32>) -> Vector2<i32> {
+        -turn_right(dir)
+    }
+}
+
+struct UTurn;
+
+impl GuardRule for UTurn {
+    fn turn(&self, _position: &Point2<i32>, dir: Vector2<i32>) -> Vector2<i32> {
+        -dir
+    }
+}
+
+struct SeededTurn {
+    seed: u64,
+}
+
+impl GuardRule for SeededTurn {
+    fn turn(&self, position: &Point2<i32>, dir: Vector2<i32>) -> Vector2<i32> {
+        // splitmix64 over seed & state
+        let mut hash = self.seed
+            ^ (position.x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            ^ (position.y as u64).wrapping_mul(0xBF58_476D_1CE4_E5B9)
+            ^ direction_index(&dir) as u64;
+
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        hash ^= hash >> 31;
+
+        if hash & 1 == 0 { turn_right(dir) } else { -turn_right(dir) }
+    }
+}
+
+fn parse_rule(name: &str) -> Box<dyn GuardRule> {
+    match name {
+        "right" => Box::new(TurnRight),
+        "left" => Box::new(TurnLeft),
+        "u-turn" => Box::new(UTurn),
+        _ => match name.strip_prefix("seeded:") {
+            Some(seed) => Box::new(SeededTurn { seed: seed.parse().unwrap() }),
+            None => panic!("unknown guard rule {name}"),
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////
+// BitSet
+/////////////////////////////////////////////////////////////////////
+#[derive(Clone, Debug)]
+struct BitSet {
+    words: Vec<u64>,
+}
+
+impl BitSet {
+    fn new(len: usize) -> Self {
+        BitSet { words: vec![0; len.div_ceil(64)] }
+    }
+
+    // Returns true if index was not already in the set
+    fn insert(&mut self, index: usize) -> bool {
+        let word = &mut self.words[index / 64];
+        let mask = 1 << (index % 64);
+        let added = *word & mask == 0;
+
+        *word |= mask;
+        added
+    }
+
+    fn len(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    fn clear(&mut self) {
+        self.words.fill(0);
+    }
+}
+
+/////////////////////////////////////////////////////////////////////
+// Area
+/////////////////////////////////////////////////////////////////////
+#[derive(Clone, Copy, Debug)]
+struct Area {
+    width: i32,
+    height: i32,
+}
+
+impl Area {
+    fn of(map: &InputMap) -> Self {
+        Area {
+            width: map.first().map_or(0, |row| row.len() as i32),
+            height: map.len() as i32,
+        }
+    }
+
+    fn cells(&self) -> usize {
+        (self.width * self.height) as usize
+    }
+
+    fn cell_index(&self, point: &Point2<i32>) -> usize {
+        (point.y * self.width + point.x) as usize
+    }
+
+    fn state_index(&self, point: &Point2<i32>, dir: &Vector2<i32>) -> usize {
+        self.cell_index(point) * 4 + direction_index(dir)
+    }
+}
+
 /////////////////////////////////////////////////////////////////////
 // GuardMap
 /////////////////////////////////////////////////////////////////////
@@ -50,20 +203,146 @@ impl GuardMap for InputMap {
 }
 
 /////////////////////////////////////////////////////////////////////
-// CorrectedMap
+// OverlayMap
 /////////////////////////////////////////////////////////////////////
-struct CorrectedMap<'m> {
-    map: &'m InputMap,
-    object: Point2<i32>,
+#[derive(Clone, Debug)]
+enum Layer {
+    Add(HashSet<Point2<i32>>),
+    Remove(HashSet<Point2<i32>>),
+    // Guard may still walk there, but no obstruction can be placed
+    Forbid(HashSet<Point2<i32>>),
+}
+
+struct OverlayMap<'m, M: GuardMap> {
+    base: &'m M,
+    layers: Vec<Layer>,
 }
 
-impl<'m> GuardMap for CorrectedMap<'m> {
+impl<'m, M: GuardMap> OverlayMap<'m, M> {
+    fn new(base: &'m M) -> Self {
+        OverlayMap { base, layers: Vec::new() }
+    }
+
+    fn add_obstacles(mut self, points: impl IntoIterator<Item = Point2<i32>>) -> Self {
+        self.layers.push(Layer::Add(points.into_iter().collect()));
+        self
+    }
+
+    fn remove_obstacles(mut self, points: impl IntoIterator<Item = Point2<i32>>) -> Self {
+        self.layers.push(Layer::Remove(points.into_iter().collect()));
+        self
+    }
+
+    fn forbid(mut self, points: impl IntoIterator<Item = Point2<i32>>) -> Self {
+        self.layers.push(Layer::Forbid(points.into_iter().collect()));
+        self
+    }
+
+    fn look_at_layer(&self, depth: usize, point: &Point2<i32>) -> Option<&char> {
+        let Some(layer) = depth.checked_sub(1).map(|idx| &self.layers[idx]) else {
+            return self.base.look_at(point);
+        };
+
+        let below = self.look_at_layer(depth - 1, point)?;
+
+        match layer {
+            Layer::Add(points) if points.contains(point) => Some(&'#'),
+            Layer::Remove(points) if points.contains(point) && below == &'#' => Some(&'.'),
+            Layer::Forbid(points) if points.contains(point) && below != &'#' => Some(&'X'),
+            _ => Some(below),
+        }
+    }
+}
+
+impl<'m, M: GuardMap> GuardMap for OverlayMap<'m, M> {
     fn look_at(&self, point: &Point2<i32>) -> Option<&char> {
-        if point == &self.object {
-            return Some(&'#');
+        self.look_at_layer(self.layers.len(), point)
+    }
+}
+
+fn can_obstruct<M: GuardMap>(map: &M, point: &Point2<i32>) -> bool {
+    map.look_at(point) == Some(&'.')
+}
+
+fn parse_points(points: &str) -> Vec<Point2<i32>> {
+    points.split(';')
+        .map(|point| {
+            let (x, y) = point.split_once(',').unwrap();
+            point![x.parse().unwrap(), y.parse().unwrap()]
+        })
+        .collect()
+}
+
+/////////////////////////////////////////////////////////////////////
+// JumpTable
+/////////////////////////////////////////////////////////////////////
+struct JumpTable {
+    area: Area,
+    // Last free cell before the next obstacle, for each cell and direction. None if guard leaves the map
+    stops: Vec<Option<Point2<i32>>>,
+}
+
+impl JumpTable {
+    fn new<M: GuardMap>(map: &M, area: Area) -> Self {
+        let mut stops = vec![None; area.cells() * 4];
+
+        for dir in [UP, RIGHT, DOWN, LEFT] {
+            // Sweep against direction, so the next cell is always computed first
+            let xs: Vec<i32> = if dir.x > 0 { (0..area.width).rev().collect() } else { (0..area.width).collect() };
+            let ys: Vec<i32> = if dir.y > 0 { (0..area.height).rev().collect() } else { (0..area.height).collect() };
+
+            for &y in &ys {
+                for &x in &xs {
+                    let pos = point![x, y];
+                    let next = pos + dir;
+
+                    stops[area.state_index(&pos, &dir)] = match map.look_at(&next) {
+                        Some(&'#') => Some(pos),
+                        Some(_) => stops[area.state_index(&next, &dir)],
+                        None => None,
+                    };
+                }
+            }
+        }
+
+        JumpTable { area, stops }
+    }
+
+    fn jump(&self, pos: &Point2<i32>, dir: &Vector2<i32>, objects: &[Point2<i32>]) -> Option<Point2<i32>> {
+        let mut stop = self.stops[self.area.state_index(pos, dir)];
+        let mut limit = stop.map(|stop| (stop - pos).dot(dir));
+
+        // Patch for added objects, if they stand between guard and its stop
+        for object in objects {
+            let dist = (object - pos).dot(dir);
+
+            if dist > 0 && *object == pos + dist * dir && limit.is_none_or(|limit| dist <= limit) {
+                stop = Some(object - dir);
+                limit = Some(dist - 1);
+            }
+        }
+
+        stop
+    }
+
+    fn visited(&self) -> BitSet {
+        BitSet::new(self.area.cells() * 4)
+    }
+
+    fn is_loop(&self, objects: &[Point2<i32>], rule: &dyn GuardRule, mut pos: Point2<i32>, mut dir: Vector2<i32>, visited: &mut BitSet) -> bool {
+        visited.clear();
+
+        while let Some(stop) = self.jump(&pos, &dir, objects) {
+            pos = stop;
+
+            if !visited.insert(self.area.state_index(&pos, &dir)) {
+                return true;
+            }
+
+            dir = rule.turn(&pos, dir);
         }
 
-        self.map.look_at(point)
+        false
     }
 }
 
@@ -72,6 +351,7 @@ impl<'m> GuardMap for CorrectedMap<'m> {
 /////////////////////////////////////////////////////////////////////
 struct Guard<'a, M: GuardMap> {
     map: &'a M,
+    rule: &'a dyn GuardRule,
     position: Point2<i32>,
     direction: Vector2<i32>,
 }
@@ -80,11 +360,11 @@ impl<'a, M: GuardMap> Iterator for Guard<'a, M> {
     type Item = (Point2<i32>, Vector2<i32>);
 
     fn next(&mut self) -> Option<Self::Item> {
-        loop {
+        for _ in 0..4 {
             let next = self.position + self.direction;
 
             match self.map.look_at(&next) {
-                Some(&'#') => self.direction = turn_right(self.direction),
+                Some(&'#') => self.direction = self.rule.turn(&self.position, self.direction),
                 Some(_) => {
                     self.position = next;
                     return Some((next, self.direction));
@@ -92,6 +372,9 @@ impl<'a, M: GuardMap> Iterator for Guard<'a, M> {
                 None => return None,
             }
         }
+
+        // Guard is stuck, it stays in place (and so will loop)
+        Some((self.position, self.direction))
     }
 }
 
@@ -100,54 +383,567 @@ impl<'a, M: GuardMap> FusedIterator for Guard<'a, M> {}
 /////////////////////////////////////////////////////////////////////
 // main
 /////////////////////////////////////////////////////////////////////
-fn patrol<M: GuardMap>(map: &M, start: Point2<i32>) -> Option<HashMap<Point2<i32>, HashSet<Vector2<i32>>>> {
-    let mut visited = HashMap::new();
-    visited.insert(start, HashSet::from([UP]));
+// State of the guard just before entering a cell for the first time
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    from: Point2<i32>,
+    direction: Vector2<i32>,
+    cell: Point2<i32>,
+}
 
-    let guard = Guard { map, position: start, direction: UP };
+struct Patrol {
+    visited: BitSet,
+    entries: Vec<Entry>,
+}
 
-    for (pos, dir) in guard {
-        let dirs = visited.entry(pos).or_default();
+fn patrol<M: GuardMap>(map: &M, area: Area, rule: &dyn GuardRule, start: Point2<i32>, direction: Vector2<i32>) -> Option<Patrol> {
+    let mut visited = BitSet::new(area.cells());
+    let mut states = BitSet::new(area.cells() * 4);
+    let mut entries = Vec::new();
+    let mut from = start;
 
-        if dirs.contains(&dir) {
+    visited.insert(area.cell_index(&start));
+    states.insert(area.state_index(&start, &direction));
+
+    let guard = Guard { map, rule, position: start, direction };
+
+    for (pos, dir) in guard {
+        if !states.insert(area.state_index(&pos, &dir)) {
             return None;
-        } else {
-            dirs.insert(dir);
+        }
+
+        if visited.insert(area.cell_index(&pos)) {
+            entries.push(Entry { from, direction: dir, cell: pos });
+        }
+
+        from = pos;
+    }
+
+    Some(Patrol { visited, entries })
+}
+
+// Every guard state in order, until it leaves the map or starts looping
+struct Route {
+    states: Vec<(Point2<i32>, Vector2<i32>)>,
+    cycle: Option<usize>,
+}
+
+fn route<M: GuardMap>(map: &M, rule: &dyn GuardRule, start: Point2<i32>, direction: Vector2<i32>) -> Route {
+    let mut states = vec![(start, direction)];
+    let mut indexes = HashMap::from([((start, direction), 0)]);
+
+    let guard = Guard { map, rule, position: start, direction };
+
+    for state in guard {
+        if let Some(&idx) = indexes.get(&state) {
+            return Route { states, cycle: Some(idx) };
+        }
+
+        indexes.insert(state, states.len());
+        states.push(state);
+    }
+
+    Route { states, cycle: None }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+struct Collision {
+    tick: usize,
+    cell: Point2<i32>,
+    // Cell the first guard moved to, when guards swap cells and pass through each other
+    passing: Option<Point2<i32>>,
+    guards: Vec<usize>,
+}
+
+struct SquadPatrol {
+    visited: BitSet,
+    collisions: Vec<Collision>,
+    looping: bool,
+}
+
+// Moves all guards simultaneously, one step each per tick
+fn patrol_squad<M: GuardMap>(map: &M, area: Area, rule: &dyn GuardRule, starts: &[(Point2<i32>, Vector2<i32>)]) -> SquadPatrol {
+    let mut guards = starts.iter()
+        .map(|&(position, direction)| Guard { map, rule, position, direction })
+        .collect::<Vec<_>>();
+
+    let mut states = starts.iter().copied().map(Some).collect::<Vec<_>>();
+    let mut seen = HashSet::from([states.clone()]);
+    let mut visited = BitSet::new(area.cells());
+    let mut collisions = Vec::new();
+
+    for (start, _) in starts {
+        visited.insert(area.cell_index(start));
+    }
+
+    for tick in 1.. {
+        let previous = states.clone();
+
+        for (guard, state) in guards.iter_mut().zip(&mut states) {
+            *state = guard.next();
+        }
+
+        if states.iter().all(Option::is_none) {
+            return SquadPatrol { visited, collisions, looping: false };
+        }
+
+        let mut cells: HashMap<Point2<i32>, Vec<usize>> = HashMap::new();
+
+        for (idx, (pos, _)) in states.iter().enumerate().filter_map(|(idx, state)| Some((idx, (*state)?))) {
+            visited.insert(area.cell_index(&pos));
+            cells.entry(pos).or_default().push(idx);
+        }
+
+        let mut tick_collisions = cells.into_iter()
+            .filter(|(_, guards)| guards.len() > 1)
+            .map(|(cell, guards)| Collision { tick, cell, passing: None, guards })
+            .collect::<Vec<_>>();
+
+        // Guards swapping cells collide on the edge between them
+        let mut moves: HashMap<(Point2<i32>, Point2<i32>), usize> = HashMap::new();
+
+        for (idx, (before, after)) in previous.iter().zip(&states).enumerate() {
+            let (Some((from, _)), Some((to, _))) = (before, after) else {
+                continue;
+            };
+
+            if from == to {
+                continue;
+            }
+
+            if let Some(&other) = moves.get(&(*to, *from)) {
+                tick_collisions.push(Collision { tick, cell: *to, passing: Some(*from), guards: vec![other, idx] });
+            }
+
+            moves.insert((*from, *to), idx);
+        }
+
+        tick_collisions.sort_by_key(|collision| collision.guards[0]);
+        collisions.extend(tick_collisions);
+
+        if !seen.insert(states.clone()) {
+            return SquadPatrol { visited, collisions, looping: true };
         }
     }
 
-    Some(visited)
+    unreachable!()
+}
+
+/////////////////////////////////////////////////////////////////////
+// ObstructionSearch
+/////////////////////////////////////////////////////////////////////
+fn ordered_pair(a: Point2<i32>, b: Point2<i32>) -> (Point2<i32>, Point2<i32>) {
+    if (a.y, a.x) <= (b.y, b.x) { (a, b) } else { (b, a) }
+}
+
+struct ObstructionSearch<'a, M: GuardMap + Sync> {
+    map: &'a M,
+    area: Area,
+    jumps: JumpTable,
+    rule: &'a dyn GuardRule,
+    start: Point2<i32>,
+    direction: Vector2<i32>,
+}
+
+impl<'a, M: GuardMap + Sync> ObstructionSearch<'a, M> {
+    fn new(map: &'a M, area: Area, rule: &'a dyn GuardRule, start: Point2<i32>, direction: Vector2<i32>) -> Self {
+        ObstructionSearch {
+            map,
+            area,
+            jumps: JumpTable::new(map, area),
+            rule,
+            start,
+            direction,
+        }
+    }
+
+    fn patrol(&self, objects: &[Point2<i32>]) -> Option<Patrol> {
+        let map = OverlayMap::new(self.map).add_obstacles(objects.iter().copied());
+        patrol(&map, self.area, self.rule, self.start, self.direction)
+    }
+
+    fn route(&self, objects: &[Point2<i32>]) -> Route {
+        let map = OverlayMap::new(self.map).add_obstacles(objects.iter().copied());
+        route(&map, self.rule, self.start, self.direction)
+    }
+
+    fn is_candidate(&self, entry: &Entry, excluded: &HashSet<Point2<i32>>) -> bool {
+        can_obstruct(self.map, &entry.cell) && !excluded.contains(&entry.cell)
+    }
+
+    // Every single obstruction making the guard loop
+    fn loops(&self) -> Vec<Point2<i32>> {
+        let Some(patrol) = self.patrol(&[]) else {
+            return Vec::new();
+        };
+
+        patrol.entries.par_iter()
+            .filter(|entry| self.is_candidate(entry, &HashSet::new()))
+            .map_init(|| self.jumps.visited(), |visited, entry| {
+                self.jumps.is_loop(&[entry.cell], self.rule, entry.from, entry.direction, visited)
+                    .then_some(entry.cell)
+            })
+            .flatten()
+            .collect()
+    }
+
+    // Smallest set of obstructions (up to max) making the guard loop
+    fn minimal_trap(&self, max: usize) -> Option<Vec<Point2<i32>>> {
+        (0..=max).find_map(|count| {
+            let mut placed = Vec::new();
+            self.trap(&mut placed, count).then_some(placed)
+        })
+    }
+
+    fn trap(&self, placed: &mut Vec<Point2<i32>>, remaining: usize) -> bool {
+        let Some(patrol) = self.patrol(placed) else {
+            return true;
+        };
+
+        if remaining == 0 {
+            return false;
+        }
+
+        let mut visited = self.jumps.visited();
+
+        for entry in patrol.entries.iter().filter(|entry| self.is_candidate(entry, &HashSet::new())) {
+            placed.push(entry.cell);
+
+            let found = if remaining == 1 {
+                self.jumps.is_loop(placed, self.rule, entry.from, entry.direction, &mut visited)
+            } else {
+                self.trap(placed, remaining - 1)
+            };
+
+            if found {
+                return true;
+            }
+
+            placed.pop();
+        }
+
+        false
+    }
+
+    // Pairs of obstructions making the guard loop, while none of them does alone
+    fn loop_pairs(&self) -> Vec<(Point2<i32>, Point2<i32>)> {
+        let singles = self.loops().into_iter().collect::<HashSet<_>>();
+        let Some(patrol) = self.patrol(&[]) else {
+            return Vec::new();
+        };
+
+        // First obstruction met is on the original path, the second on the path deviated by the first
+        let mut pairs = patrol.entries.par_iter()
+            .filter(|first| self.is_candidate(first, &singles))
+            .flat_map_iter(|first| {
+                let mut visited = self.jumps.visited();
+                let deviated = self.patrol(&[first.cell]).map_or(Vec::new(), |patrol| patrol.entries);
+
+                deviated.into_iter()
+                    .filter(|second| self.is_candidate(second, &singles))
+                    .filter(|second| self.jumps.is_loop(&[first.cell, second.cell], self.rule, second.from, second.direction, &mut visited))
+                    .map(|second| ordered_pair(first.cell, second.cell))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        pairs.sort_by_key(|(a, b)| (a.y, a.x, b.y, b.x));
+        pairs.dedup();
+        pairs
+    }
+}
+
+/////////////////////////////////////////////////////////////////////
+// Rendering
+/////////////////////////////////////////////////////////////////////
+struct Rendering {
+    cells: Vec<Vec<(char, Style)>>,
+}
+
+impl Rendering {
+    fn new<M: GuardMap>(map: &M, area: Area) -> Self {
+        let cells = (0..area.height)
+            .map(|y| (0..area.width)
+                .map(|x| match map.look_at(&point![x, y]) {
+                    Some(&'.') => ('.', Style::new().bright_black()),
+                    Some(&c) => (c, Style::new()),
+                    None => (' ', Style::new()),
+                })
+                .collect())
+            .collect();
+
+        Rendering { cells }
+    }
+
+    fn mark(&mut self, point: &Point2<i32>, c: char, style: Style) {
+        self.cells[point.y as usize][point.x as usize] = (c, style);
+    }
+
+    fn restyle(&mut self, point: &Point2<i32>, style: Style) {
+        self.cells[point.y as usize][point.x as usize].1 = style;
+    }
+
+    fn draw_route(&mut self, route: &Route, style: Style) {
+        let mut axes: HashMap<Point2<i32>, (bool, bool)> = HashMap::new();
+
+        // Each cell is marked with the axis of moves entering and leaving it
+        for (idx, (pos, dir)) in route.states.iter().enumerate() {
+            let leaving = route.states.get(idx + 1).map_or(*dir, |(_, next)| *next);
+            let cell = axes.entry(*pos).or_default();
+
+            for d in [dir, &leaving] {
+                if d.x == 0 { cell.0 = true } else { cell.1 = true }
+            }
+        }
+
+        for (pos, axes) in axes {
+            if parse_direction(self.cells[pos.y as usize][pos.x as usize].0).is_some() {
+                continue;
+            }
+
+            let c = match axes {
+                (true, false) => '|',
+                (false, true) => '-',
+                _ => '+',
+            };
+
+            self.mark(&pos, c, style);
+        }
+    }
+
+    fn print(&self) {
+        for row in &self.cells {
+            for (c, style) in row {
+                print!("{}", c.style(*style));
+            }
+
+            println!();
+        }
+    }
+
+    fn to_text(&self) -> String {
+        self.cells.iter()
+            .map(|row| row.iter().map(|(c, _)| *c).collect::<String>() + "\n")
+            .collect()
+    }
 }
 
 fn main() {
+    let arg = |name: &str| std::env::args().find_map(|arg| arg.strip_prefix(name).map(str::to_string));
+    let rule = arg("--rule=").map_or_else(|| Box::new(TurnRight) as Box<dyn GuardRule>, |name| parse_rule(&name));
+
     // Load map
-    let map: Vec<Vec<char>> = read_lines!("day-06/input.txt")
+    let input: Vec<Vec<char>> = read_lines!("day-06/input.txt")
         .map(|line| line.chars().collect())
         .collect();
 
-    // Search start
-    let mut start = point![0, 0];
+    let area = Area::of(&input);
+    let map = OverlayMap::new(&input)
+        .add_obstacles(arg("--add=").map(|points| parse_points(&points)).unwrap_or_default())
+        .remove_obstacles(arg("--remove=").map(|points| parse_points(&points)).unwrap_or_default())
+        .forbid(arg("--forbid=").map(|points| parse_points(&points)).unwrap_or_default());
 
-    for (y, row) in map.iter().enumerate() {
+    // Search guards
+    let mut guards = Vec::new();
+
+    for (y, row) in input.iter().enumerate() {
         for (x, c) in row.iter().enumerate() {
-            if *c == '^' {
-                start = point![x as i32, y as i32];
+            if let Some(dir) = parse_direction(*c) {
+                guards.push((point![x as i32, y as i32], dir));
+            }
+        }
+    }
+
+    if guards.len() > 1 {
+        let squad = patrol_squad(&map, area, rule.as_ref(), &guards);
+
+        for collision in &squad.collisions {
+            match collision.passing {
+                Some(to) => println!(
+                    "tick {}: guards {:?} pass each other between ({},{}) and ({},{})",
+                    collision.tick, collision.guards, collision.cell.x, collision.cell.y, to.x, to.y,
+                ),
+                None => println!("tick {}: guards {:?} collide at ({},{})", collision.tick, collision.guards, collision.cell.x, collision.cell.y),
+            }
+        }
+
+        println!("{} guards visited {} cells{}", guards.len(), squad.visited.len(), if squad.looping { ", looping forever" } else { "" });
+        return;
+    }
+
+    let (start, direction) = guards[0];
+    let search = ObstructionSearch::new(&map, area, rule.as_ref(), start, direction);
+
+    if let Some(max) = arg("--trap") {
+        let max = max.strip_prefix('=').map_or(3, |max| max.parse().unwrap());
+
+        match search.minimal_trap(max) {
+            Some(objects) => println!("{} obstruction(s) trap the guard: {:?}", objects.len(), objects.iter().map(|p| (p.x, p.y)).collect::<Vec<_>>()),
+            None => println!("no trap with up to {max} obstruction(s)"),
+        }
+
+        return;
+    }
+
+    if arg("--loop-pairs").is_some() {
+        let pairs = search.loop_pairs();
+
+        for (a, b) in &pairs {
+            println!("({},{}) + ({},{})", a.x, a.y, b.x, b.y);
+        }
+
+        println!("{} pairs of obstructions make the guard loop", pairs.len());
+        return;
+    }
+
+    if arg("--render").is_some() || arg("--show-loop=").is_some() {
+        let mut rendering = Rendering::new(&map, area);
+
+        if let Some(object) = arg("--show-loop=").map(|point| parse_points(&point)[0]) {
+            let route = search.route(&[object]);
+            rendering.draw_route(&route, Style::new().blue());
+
+            for (pos, _) in &route.states[route.cycle.unwrap_or(route.states.len())..] {
+                rendering.restyle(pos, Style::new().red().bold());
             }
+
+            rendering.mark(&object, 'O', Style::new().yellow().bold());
+        } else {
+            rendering.draw_route(&search.route(&[]), Style::new().blue());
+
+            for object in search.loops() {
+                rendering.mark(&object, 'O', Style::new().yellow().bold());
+            }
+        }
+
+        match arg("--output=") {
+            Some(path) => std::fs::write(&path, rendering.to_text()).unwrap_or_else(|_| panic!("error writing file {path}")),
+            None => rendering.print(),
         }
+
+        return;
     }
 
     // Part 1
-    let visited = patrol(&map, start).unwrap();
-    println!("part 01: {}", visited.len());
+    let now = Instant::now();
+    let Some(patrol) = search.patrol(&[]) else {
+        println!("guard is looping forever");
+        return;
+    };
+
+    println!("part 01: {} ({:.2?})", patrol.visited.len(), now.elapsed());
 
     // Part 2
-    let part2 = visited.keys()
-        .filter(|&pos| pos != &start)
-        .filter(|&pos| {
-            let map = CorrectedMap { map: &map, object: *pos };
-            patrol(&map, start).is_none()
-        })
-        .count();
+    let now = Instant::now();
+    let part2 = search.loops().len();
+
+    println!("part 02: {part2} ({:.2?})", now.elapsed());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "....#.....
+.........#
+..........
+..#.......
+.......#..
+..........
+.#..^.....
+........#.
+#.........
+......#...";
+
+    #[test]
+    fn example_loops() {
+        let map: InputMap = EXAMPLE.lines().map(|line| line.chars().collect()).collect();
+        let area = Area::of(&map);
+        let search = ObstructionSearch::new(&map, area, &TurnRight, point![4, 6], UP);
+
+        assert_eq!(search.patrol(&[]).unwrap().visited.len(), 41);
+        assert_eq!(search.loops().len(), 6);
+        assert_eq!(search.minimal_trap(2).map(|objects| objects.len()), Some(1));
+    }
+
+    #[test]
+    fn render_example_route() {
+        let map: InputMap = EXAMPLE.lines().map(|line| line.chars().collect()).collect();
+        let area = Area::of(&map);
+        let search = ObstructionSearch::new(&map, area, &TurnRight, point![4, 6], UP);
+
+        let mut rendering = Rendering::new(&map, area);
+        rendering.draw_route(&search.route(&[point![3, 6]]), Style::new());
+        rendering.mark(&point![3, 6], 'O', Style::new());
 
-    println!("part 02: {part2}");
+        assert_eq!(rendering.to_text(), "\
+....#.....
+....+---+#
+....|...|.
+..#.|...|.
+....|..#|.
+....|...|.
+.#.O^---+.
+........#.
+#.........
+......#...
+");
+    }
+
+    #[test]
+    fn overlay_layers() {
+        let map: InputMap = EXAMPLE.lines().map(|line| line.chars().collect()).collect();
+        let overlay = OverlayMap::new(&map)
+            .add_obstacles([point![0, 0]])
+            .remove_obstacles([point![4, 0], point![1, 0]])
+            .forbid([point![2, 0], point![9, 1]]);
+
+        assert_eq!(overlay.look_at(&point![0, 0]), Some(&'#'));
+        assert_eq!(overlay.look_at(&point![4, 0]), Some(&'.'));
+        assert_eq!(overlay.look_at(&point![1, 0]), Some(&'.'));
+        assert_eq!(overlay.look_at(&point![2, 0]), Some(&'X'));
+        assert_eq!(overlay.look_at(&point![9, 1]), Some(&'#'));
+        assert_eq!(overlay.look_at(&point![10, 0]), None);
+    }
+
+    #[test]
+    fn pairs_of_obstructions() {
+        let map: InputMap = [".....", ".....", "..^..", ".....", "....."].iter().map(|line| line.chars().collect()).collect();
+        let search = ObstructionSearch::new(&map, Area::of(&map), &TurnRight, point![2, 2], UP);
+
+        assert!(search.loops().is_empty());
+        assert_eq!(search.loop_pairs(), vec![]);
+        assert_eq!(search.minimal_trap(2), None);
+
+        let walled = OverlayMap::new(&map).add_obstacles([point![2, 0], point![4, 1]]);
+        let search = ObstructionSearch::new(&walled, Area::of(&map), &TurnRight, point![2, 2], UP);
+
+        assert_eq!(search.minimal_trap(2).map(|objects| objects.len()), Some(2));
+        assert!(search.loop_pairs().contains(&(point![1, 2], point![3, 3])));
+    }
+
+    #[test]
+    fn squad_collision() {
+        let map: InputMap = ["#....", ">...<", "....#"].iter().map(|line| line.chars().collect()).collect();
+        let squad = patrol_squad(&map, Area::of(&map), &TurnRight, &[(point![0, 1], RIGHT), (point![4, 1], LEFT)]);
+
+        assert_eq!(squad.collisions, vec![Collision { tick: 2, cell: point![2, 1], passing: None, guards: vec![0, 1] }]);
+        assert!(!squad.looping);
+    }
+
+    #[test]
+    fn squad_swap_collision() {
+        let map: InputMap = ["#....", ">..<.", "....#"].iter().map(|line| line.chars().collect()).collect();
+        let squad = patrol_squad(&map, Area::of(&map), &TurnRight, &[(point![0, 1], RIGHT), (point![3, 1], LEFT)]);
+
+        assert_eq!(squad.collisions, vec![Collision { tick: 2, cell: point![1, 1], passing: Some(point![2, 1]), guards: vec![0, 1] }]);
+    }
+
+    #[test]
+    fn u_turn_loops() {
+        let map: InputMap = ["#.^.#"].iter().map(|line| line.chars().collect()).collect();
+
+        assert!(patrol(&map, Area::of(&map), &UTurn, point![2, 0], RIGHT).is_none());
+        assert!(patrol(&map, Area::of(&map), &TurnRight, point![2, 0], RIGHT).is_some());
+    }
 }
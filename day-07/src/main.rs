@@ -1,4 +1,6 @@
-use std::collections::VecDeque;
+use num_bigint::BigUint;
+use std::fmt::{self, Debug, Display};
+use std::str::FromStr;
 use std::time::Instant;
 
 macro_rules! read_lines {
@@ -11,88 +13,573 @@ macro_rules! read_lines {
     };
 }
 
-fn concatenate(a: u64, b: u64) -> u64 {
-    a * (10u64.pow(b.ilog10() + 1)) + b
+/////////////////////////////////////////////////////////////////////
+// Number
+/////////////////////////////////////////////////////////////////////
+// Arithmetic used by operators, None means overflow (or undefined result)
+trait Number: Clone + Ord + Display + FromStr<Err: Debug> {
+    fn zero() -> Self;
+    fn checked_add(&self, rhs: &Self) -> Option<Self>;
+    fn checked_sub(&self, rhs: &Self) -> Option<Self>;
+    fn checked_mul(&self, rhs: &Self) -> Option<Self>;
+    fn checked_rem(&self, rhs: &Self) -> Option<Self>;
+    fn div(&self, rhs: &Self) -> Self;
+    fn xor(&self, rhs: &Self) -> Self;
+
+    // 10 to the power of the number of digits
+    fn shift(&self) -> Option<Self>;
 }
 
-fn is_calibrated(terms: &[u64], result: u64) -> bool {
-    let mut queue = VecDeque::from([(terms[0], 1)]);
+macro_rules! impl_number {
+    ($type:ty) => {
+        impl Number for $type {
+            fn zero() -> Self {
+                0
+            }
 
-    while !queue.is_empty() {
-        let (val, idx) = queue.pop_front().unwrap();
+            fn checked_add(&self, rhs: &Self) -> Option<Self> {
+                <$type>::checked_add(*self, *rhs)
+            }
 
-        if let Some(&term) = terms.get(idx) {
-            for res in [val + term, val * term] {
-                if res > result {
-                    continue;
-                }
+            fn checked_sub(&self, rhs: &Self) -> Option<Self> {
+                <$type>::checked_sub(*self, *rhs)
+            }
 
-                queue.push_back((res, idx + 1));
+            fn checked_mul(&self, rhs: &Self) -> Option<Self> {
+                <$type>::checked_mul(*self, *rhs)
+            }
+
+            fn checked_rem(&self, rhs: &Self) -> Option<Self> {
+                <$type>::checked_rem(*self, *rhs)
+            }
+
+            fn div(&self, rhs: &Self) -> Self {
+                self / rhs
+            }
+
+            fn xor(&self, rhs: &Self) -> Self {
+                self ^ rhs
+            }
+
+            fn shift(&self) -> Option<Self> {
+                <$type>::checked_pow(10, self.checked_ilog10().unwrap_or(0) + 1)
             }
-        } else if val == result { // Found !
-            return true;
         }
+    };
+}
+
+impl_number!(u64);
+impl_number!(u128);
+
+impl Number for BigUint {
+    fn zero() -> Self {
+        BigUint::ZERO
+    }
+
+    fn checked_add(&self, rhs: &Self) -> Option<Self> {
+        Some(self + rhs)
+    }
+
+    fn checked_sub(&self, rhs: &Self) -> Option<Self> {
+        (self >= rhs).then(|| self - rhs)
     }
 
-    false
+    fn checked_mul(&self, rhs: &Self) -> Option<Self> {
+        Some(self * rhs)
+    }
+
+    fn checked_rem(&self, rhs: &Self) -> Option<Self> {
+        (rhs != &BigUint::ZERO).then(|| self % rhs)
+    }
+
+    fn div(&self, rhs: &Self) -> Self {
+        self / rhs
+    }
+
+    fn xor(&self, rhs: &Self) -> Self {
+        self ^ rhs
+    }
+
+    fn shift(&self) -> Option<Self> {
+        Some(BigUint::from(10u32).pow(self.to_string().len() as u32))
+    }
+}
+
+/////////////////////////////////////////////////////////////////////
+// Operator
+/////////////////////////////////////////////////////////////////////
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Operator {
+    Add,
+    Mul,
+    Concat,
+    Sub,
+    Div,
+    Xor,
 }
 
-fn is_calibrated_2(terms: &[u64], result: u64) -> bool {
-    let mut queue = VecDeque::from([(terms[0], 1)]);
+impl Operator {
+    fn parse(symbol: &str) -> Operator {
+        match symbol {
+            "+" => Operator::Add,
+            "*" => Operator::Mul,
+            "||" => Operator::Concat,
+            "-" => Operator::Sub,
+            "/" => Operator::Div,
+            "^" => Operator::Xor,
+            _ => panic!("unknown operator {symbol}"),
+        }
+    }
 
-    while !queue.is_empty() {
-        let (val, idx) = queue.pop_back().unwrap();
+    fn symbol(&self) -> &'static str {
+        match self {
+            Operator::Add => "+",
+            Operator::Mul => "*",
+            Operator::Concat => "||",
+            Operator::Sub => "-",
+            Operator::Div => "/",
+            Operator::Xor => "^",
+        }
+    }
 
-        if let Some(&term) = terms.get(idx) {
-            for res in [val + term, val * term, concatenate(val, term)] {
-                if res > result {
-                    continue;
+    // None if operation is not defined on given operands, or overflows
+    fn apply<N: Number>(&self, a: &N, b: &N) -> Option<N> {
+        match self {
+            Operator::Add => a.checked_add(b),
+            Operator::Mul => a.checked_mul(b),
+            Operator::Concat => a.checked_mul(&b.shift()?)?.checked_add(b),
+            Operator::Sub => a.checked_sub(b),
+            Operator::Div => (a.checked_rem(b)? == N::zero()).then(|| a.div(b)),
+            Operator::Xor => Some(a.xor(b)),
+        }
+    }
+
+    // Value before applying operator with term, None if res cannot come from this operator.
+    // Multiplying by zero loses the previous value, the solver handles that case.
+    fn undo<N: Number>(&self, res: &N, term: &N) -> Option<N> {
+        match self {
+            Operator::Add => res.checked_sub(term),
+            Operator::Mul if term == &N::zero() => None,
+            Operator::Mul => (res.checked_rem(term)? == N::zero()).then(|| res.div(term)),
+            Operator::Concat => {
+                let shift = term.shift()?;
+                let head = res.checked_sub(term)?;
+
+                (head.checked_rem(&shift)? == N::zero()).then(|| head.div(&shift))
+            }
+            Operator::Sub => res.checked_add(term),
+            Operator::Div if term == &N::zero() => None,
+            Operator::Div => res.checked_mul(term),
+            Operator::Xor => Some(res.xor(term)),
+        }
+    }
+
+    // With positive terms, these can only increase the value
+    fn is_increasing(&self) -> bool {
+        matches!(self, Operator::Add | Operator::Mul | Operator::Concat)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////
+// Solver
+/////////////////////////////////////////////////////////////////////
+struct Solver<'o> {
+    operators: &'o [Operator],
+    prune: bool,
+    nodes: usize,
+}
+
+impl<'o> Solver<'o> {
+    fn new(operators: &'o [Operator]) -> Self {
+        Solver {
+            operators,
+            prune: operators.iter().all(Operator::is_increasing),
+            nodes: 0,
+        }
+    }
+
+    // Applies operators from the first term
+    fn forward<N: Number>(&mut self, terms: &[N], result: &N) -> Option<Vec<Operator>> {
+        let mut ops = Vec::with_capacity(terms.len() - 1);
+
+        // Multiplying by a zero term brings the value back down, so nothing can be pruned
+        let prune = self.prune && !terms[1..].contains(&N::zero());
+
+        self.search(terms, result, prune, terms[0].clone(), &mut ops).then_some(ops)
+    }
+
+    fn search<N: Number>(&mut self, terms: &[N], result: &N, prune: bool, val: N, ops: &mut Vec<Operator>) -> bool {
+        self.nodes += 1;
+
+        let Some(term) = terms.get(ops.len() + 1) else {
+            return &val == result; // Found !
+        };
+
+        for op in self.operators {
+            // Overflow is treated like exceeding the test value
+            let Some(res) = op.apply(&val, term) else {
+                continue;
+            };
+
+            if prune && &res > result {
+                continue;
+            }
+
+            ops.push(*op);
+
+            if self.search(terms, result, prune, res, ops) {
+                return true;
+            }
+
+            ops.pop();
+        }
+
+        false
+    }
+
+    // Undoes operators from the test value, back to the first term
+    fn backward<N: Number>(&mut self, terms: &[N], result: &N) -> Option<Vec<Operator>> {
+        let mut ops = Vec::with_capacity(terms.len() - 1);
+
+        if self.unwind(terms, result.clone(), &mut ops) {
+            ops.reverse();
+            Some(ops)
+        } else {
+            None
+        }
+    }
+
+    fn unwind<N: Number>(&mut self, terms: &[N], val: N, ops: &mut Vec<Operator>) -> bool {
+        self.nodes += 1;
+
+        let (term, rest) = terms.split_last().unwrap();
+
+        if rest.is_empty() {
+            return &val == term; // Found !
+        }
+
+        for op in self.operators {
+            // Any value of the previous terms times zero gives zero, they only have to be computable
+            if op == &Operator::Mul && term == &N::zero() {
+                let mut prefix = Vec::with_capacity(rest.len() - 1);
+
+                if val == N::zero() && self.evaluate(rest, rest[0].clone(), &mut prefix) {
+                    ops.push(*op);
+                    ops.extend(prefix.into_iter().rev());
+                    return true;
                 }
 
-                queue.push_back((res, idx + 1));
+                continue;
+            }
+
+            let Some(prev) = op.undo(&val, term) else {
+                continue;
+            };
+
+            ops.push(*op);
+
+            if self.unwind(rest, prev, ops) {
+                return true;
             }
-        } else if val == result { // Found !
+
+            ops.pop();
+        }
+
+        false
+    }
+
+    // Applies operators from the first term, until all terms are used without any failing operation
+    fn evaluate<N: Number>(&mut self, terms: &[N], val: N, ops: &mut Vec<Operator>) -> bool {
+        self.nodes += 1;
+
+        let Some(term) = terms.get(ops.len() + 1) else {
             return true;
+        };
+
+        for op in self.operators {
+            let Some(res) = op.apply(&val, term) else {
+                continue;
+            };
+
+            ops.push(*op);
+
+            if self.evaluate(terms, res, ops) {
+                return true;
+            }
+
+            ops.pop();
         }
+
+        false
     }
 
-    false
+    fn solve<N: Number>(&mut self, equation: &Equation<N>, backward: bool) -> Option<Vec<Operator>> {
+        if backward {
+            self.backward(&equation.terms, &equation.result)
+        } else {
+            self.forward(&equation.terms, &equation.result)
+        }
+    }
 }
 
-fn main() {
-    let now = Instant::now();
-    let mut part01 = 0;
+/////////////////////////////////////////////////////////////////////
+// Equation
+/////////////////////////////////////////////////////////////////////
+#[derive(Clone, Debug)]
+struct Equation<N> {
+    result: N,
+    terms: Vec<N>,
+}
+
+impl<N: Number> Equation<N> {
+    fn parse(line: &str) -> Result<Self, String> {
+        let number = |value: &str| value.parse::<N>().map_err(|err| format!("cannot parse {value:?} ({err:?})"));
 
-    for line in read_lines!("day-07/input.txt") {
-        let colon_idx = line.find(':').unwrap();
-        let result = line[..colon_idx].parse::<u64>().unwrap();
-        let terms = line[colon_idx + 2..]
-            .split_whitespace()
-            .map(|term| term.parse::<u64>().unwrap())
-            .collect::<Vec<_>>();
+        let (result, terms) = line.split_once(':').ok_or_else(|| format!("missing ':' in {line:?}"))?;
+        let result = number(result.trim())?;
+        let terms = terms.split_whitespace().map(number).collect::<Result<Vec<_>, _>>()?;
 
-        if is_calibrated(&terms, result) {
-            part01 += result;
+        if terms.is_empty() {
+            return Err(format!("no terms in {line:?}"));
         }
+
+        Ok(Equation { result, terms })
     }
 
-    println!("part 01: {part01} ({:.2?})", now.elapsed());
+    fn format(&self, ops: &[Operator]) -> String {
+        let mut equation = format!("{} = {}", self.result, self.terms[0]);
 
+        for (op, term) in ops.iter().zip(&self.terms[1..]) {
+            equation += &format!(" {} {term}", op.symbol());
+        }
+
+        equation
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum CalibrationError {
+    Parse { line: usize, message: String },
+    Overflow,
+}
+
+impl Display for CalibrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CalibrationError::Parse { line, message } => {
+                write!(f, "line {line}: {message}, values too large for this mode need --u128 or --bigint")
+            }
+            CalibrationError::Overflow => write!(f, "calibration total overflows, try a wider mode with --u128 or --bigint"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Calibration<N> {
+    total: N,
+    equations: usize,
+    nodes: usize,
+}
+
+fn calibrate<N: Number>(equations: &[Equation<N>], operators: &[Operator], backward: bool, verbose: bool) -> Result<Calibration<N>, CalibrationError> {
+    let mut solver = Solver::new(operators);
+    let mut calibration = Calibration { total: N::zero(), equations: 0, nodes: 0 };
+
+    for equation in equations {
+        if let Some(ops) = solver.solve(equation, backward) {
+            if verbose {
+                println!("{}", equation.format(&ops));
+            }
+
+            calibration.total = calibration.total.checked_add(&equation.result)
+                .ok_or(CalibrationError::Overflow)?;
+            calibration.equations += 1;
+        }
+    }
+
+    calibration.nodes = solver.nodes;
+    Ok(calibration)
+}
+
+/////////////////////////////////////////////////////////////////////
+// main
+/////////////////////////////////////////////////////////////////////
+fn run<N: Number>(lines: &[String]) -> Result<(), CalibrationError> {
     let now = Instant::now();
-    let mut part02 = 0;
+    let equations = lines.iter()
+        .enumerate()
+        .map(|(idx, line)| Equation::<N>::parse(line).map_err(|message| CalibrationError::Parse { line: idx + 1, message }))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    println!("parsed {} equations ({:.2?})", equations.len(), now.elapsed());
+
+    if let Some(operators) = std::env::args().find_map(|arg| arg.strip_prefix("--operators=").map(str::to_string)) {
+        let operators = operators.split(',').map(Operator::parse).collect::<Vec<_>>();
+        let calibration = calibrate(&equations, &operators, true, true)?;
+
+        println!("total: {}", calibration.total);
+        return Ok(());
+    }
+
+    let compare = std::env::args().any(|arg| arg == "--compare");
+    let parts = [
+        vec![Operator::Add, Operator::Mul],
+        vec![Operator::Add, Operator::Mul, Operator::Concat],
+    ];
+
+    for (idx, operators) in parts.iter().enumerate() {
+        if compare {
+            for (name, backward) in [("forward", false), ("backward", true)] {
+                let now = Instant::now();
+                let calibration = calibrate(&equations, operators, backward, false)?;
+
+                println!(
+                    "part {:02} {name}: {} equations, {} nodes ({:.2?})",
+                    idx + 1, calibration.equations, calibration.nodes, now.elapsed()
+                );
+            }
+        } else {
+            let now = Instant::now();
+            let calibration = calibrate(&equations, operators, true, false)?;
+
+            println!("part {:02}: {} ({:.2?})", idx + 1, calibration.total, now.elapsed());
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let lines = read_lines!("day-07/input.txt").collect::<Vec<_>>();
+
+    let result = if std::env::args().any(|arg| arg == "--bigint") {
+        run::<BigUint>(&lines)
+    } else if std::env::args().any(|arg| arg == "--u128") {
+        run::<u128>(&lines)
+    } else {
+        run::<u64>(&lines)
+    };
+
+    if let Err(err) = result {
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    for line in read_lines!("day-07/input.txt") {
-        let colon_idx = line.find(':').unwrap();
-        let result = line[..colon_idx].parse::<u64>().unwrap();
-        let terms = line[colon_idx + 2..]
-            .split_whitespace()
-            .map(|term| term.parse::<u64>().unwrap())
-            .collect::<Vec<_>>();
+    const EXAMPLE: &str = "190: 10 19
+3267: 81 40 27
+83: 17 5
+156: 15 6
+7290: 6 8 6 15
+161011: 16 10 13
+192: 17 8 14
+21037: 9 7 18 13
+292: 11 6 16 20";
 
-        if is_calibrated_2(&terms, result) {
-            part02 += result;
+    fn total<N: Number>(operators: &[Operator], backward: bool) -> N {
+        let equations = EXAMPLE.lines().map(|line| Equation::<N>::parse(line).unwrap()).collect::<Vec<_>>();
+        calibrate(&equations, operators, backward, false).unwrap().total
+    }
+
+    #[test]
+    fn example_totals() {
+        for backward in [false, true] {
+            assert_eq!(total::<u64>(&[Operator::Add, Operator::Mul], backward), 3749);
+            assert_eq!(total::<u128>(&[Operator::Add, Operator::Mul, Operator::Concat], backward), 11387);
+            assert_eq!(total::<BigUint>(&[Operator::Add, Operator::Mul, Operator::Concat], backward), BigUint::from(11387u32));
         }
     }
 
-    println!("part 02: {part02} ({:.2?})", now.elapsed());
+    #[test]
+    fn solution_equation() {
+        let operators = [Operator::Add, Operator::Mul, Operator::Concat];
+        let equation = Equation::<u64>::parse("7290: 6 8 6 15").unwrap();
+
+        let ops = Solver::new(&operators).solve(&equation, false).unwrap();
+        assert_eq!(equation.format(&ops), "7290 = 6 * 8 || 6 * 15");
+
+        let ops = Solver::new(&operators).solve(&equation, true).unwrap();
+        assert_eq!(equation.format(&ops), "7290 = 6 * 8 || 6 * 15");
+
+        let operators = [Operator::Sub, Operator::Div, Operator::Xor];
+        let equation = Equation::<u64>::parse("1: 20 5 3").unwrap();
+        let ops = Solver::new(&operators).solve(&equation, true).unwrap();
+        assert_eq!(equation.format(&ops), "1 = 20 / 5 - 3");
+    }
+
+    #[test]
+    fn overflow_exceeds_target() {
+        let operators = [Operator::Add, Operator::Mul, Operator::Concat];
+        let equation = Equation::<u64>::parse("20000000000: 10000000000 10000000000").unwrap();
+
+        for backward in [false, true] {
+            let ops = Solver::new(&operators).solve(&equation, backward).unwrap();
+            assert_eq!(equation.format(&ops), "20000000000 = 10000000000 + 10000000000");
+        }
+
+        let equation = Equation::<u64>::parse("7: 10000000000 10000000000 0").unwrap();
+        assert_eq!(Solver::new(&operators).solve(&equation, false), None);
+        assert_eq!(Solver::new(&[Operator::Sub]).solve(&equation, false), None);
+    }
+
+    #[test]
+    fn zero_terms() {
+        let cases = [
+            ("2: 5 0 2", vec![Operator::Add, Operator::Mul], true),
+            ("2: 5 3 0 2", vec![Operator::Mul, Operator::Add], true),
+            ("0: 8 0 3 0", vec![Operator::Sub, Operator::Mul], true),
+            ("0: 0 0", vec![Operator::Div], false),
+            ("0: 7 0", vec![Operator::Div, Operator::Mul], true),
+            ("1: 4 0 2", vec![Operator::Div, Operator::Mul], false),
+        ];
+
+        for (equation, operators, solvable) in cases {
+            let equation = Equation::<u64>::parse(equation).unwrap();
+
+            for backward in [false, true] {
+                let ops = Solver::new(&operators).solve(&equation, backward);
+                assert_eq!(ops.is_some(), solvable, "{equation:?} backward {backward}");
+
+                // Found operators evaluate to the test value from left to right
+                if let Some(ops) = ops {
+                    let value = ops.iter()
+                        .zip(&equation.terms[1..])
+                        .try_fold(equation.terms[0], |val, (op, term)| op.apply(&val, term));
+
+                    assert_eq!(value, Some(equation.result), "{}", equation.format(&ops));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn parse_and_overflow_errors() {
+        assert!(Equation::<u64>::parse("20000000000000000000: 1 2").unwrap_err().contains("cannot parse"));
+        assert!(Equation::<u128>::parse("20000000000000000000: 1 2").is_ok());
+        assert!(Equation::<u64>::parse("12 3 4").is_err());
+
+        let equations = ["18000000000000000000: 9000000000000000000 2", "18000000000000000000: 9000000000000000000 2"]
+            .map(|line| Equation::<u64>::parse(line).unwrap());
+
+        let calibration = calibrate(&equations, &[Operator::Mul], true, false);
+        assert_eq!(calibration.unwrap_err(), CalibrationError::Overflow);
+
+        let equations = equations.map(|equation| Equation::<u128> { result: equation.result.into(), terms: vec![9000000000000000000, 2] });
+        assert_eq!(calibrate(&equations, &[Operator::Mul], true, false).unwrap().total, 36000000000000000000);
+    }
+
+    #[test]
+    fn wider_modes() {
+        let operators = [Operator::Add, Operator::Mul, Operator::Concat];
+
+        let equation = Equation::<u128>::parse("100000000000000000000: 10000000000 10000000000").unwrap();
+        assert_eq!(Solver::new(&operators).solve(&equation, false), Some(vec![Operator::Mul]));
+
+        let equation = Equation::<BigUint>::parse("10000000000000000000000000000000000000000: 100000000000000000000 100000000000000000000").unwrap();
+        assert_eq!(Solver::new(&operators).solve(&equation, true), Some(vec![Operator::Mul]));
+    }
 }
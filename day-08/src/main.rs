@@ -1,5 +1,6 @@
 use nalgebra::{point, Point2, Vector2};
 use owo_colors::{AnsiColors, OwoColorize, Style};
+use serde::Serialize;
 use std::collections::{HashMap, HashSet};
 
 macro_rules! read_lines {
@@ -10,102 +11,569 @@ macro_rules! read_lines {
     }};
 }
 
-fn is_inside(map: &Vec<Vec<char>>, point: &Point2<i32>) -> bool {
-    map.get(point.y as usize)
-        .and_then(|row| row.get(point.x as usize))
-        .is_some()
+/////////////////////////////////////////////////////////////////////
+// Antinode Rule
+/////////////////////////////////////////////////////////////////////
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Spacing {
+    // Antinode where one antenna is ratio times as far as the other
+    Ratio(i32),
+    // Antinodes every antenna distance, up to max times (unbounded if None)
+    Harmonics(Option<u32>),
+    // Every grid point on the antenna line, stepping by the gcd-reduced distance
+    Line(Option<u32>),
 }
 
-fn search_antinodes(map: &Vec<Vec<char>>, mut first: Point2<i32>, vec: &Vector2<i32>) -> Vec<Point2<i32>> {
-    let mut result = Vec::new();
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Side {
+    Both,
+    // Beyond the second antenna of each pair only
+    Forward,
+    // Beyond the first antenna of each pair only
+    Backward,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct AntinodeRule {
+    spacing: Spacing,
+    side: Side,
+}
+
+impl AntinodeRule {
+    fn parse(spacing: &str, side: Option<&str>) -> Self {
+        let (kind, arg) = spacing.split_once(':').unwrap_or((spacing, ""));
+
+        let spacing = match kind {
+            "ratio" => Spacing::Ratio(arg.parse().unwrap()),
+            "harmonics" => Spacing::Harmonics(arg.parse().ok()),
+            "line" => Spacing::Line(arg.parse().ok()),
+            _ => panic!("unknown antinode spacing {spacing}"),
+        };
 
-    while is_inside(map, &first) {
-        result.push(first);
-        first += vec;
+        let side = match side {
+            None | Some("both") => Side::Both,
+            Some("forward") => Side::Forward,
+            Some("backward") => Side::Backward,
+            Some(side) => panic!("unknown antinode side {side}"),
+        };
+
+        AntinodeRule { spacing, side }
     }
+}
 
-    result
+/////////////////////////////////////////////////////////////////////
+// Antenna Map
+/////////////////////////////////////////////////////////////////////
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Topology {
+    Bounded,
+    Toroidal,
 }
 
-fn main() {
-    // Load map
-    let map: Vec<Vec<char>> = read_lines!("day-08/input.txt")
-        .map(|line| line.chars().collect())
-        .collect();
+struct AntennaMap {
+    width: i32,
+    height: i32,
+    topology: Topology,
+    antennas: HashMap<char, Vec<Point2<i32>>>,
+}
 
-    // Search antennas
-    let mut antennas = HashMap::new();
+impl AntennaMap {
+    fn parse(map: &[Vec<char>], topology: Topology) -> Self {
+        let mut antennas: HashMap<char, Vec<Point2<i32>>> = HashMap::new();
+
+        for (y, row) in map.iter().enumerate() {
+            for (x, &c) in row.iter().enumerate().filter(|&(_, &c)| c != '.') {
+                antennas
+                    .entry(c)
+                    .or_default()
+                    .push(point![x as i32, y as i32]);
+            }
+        }
 
-    for (y, row) in map.iter().enumerate() {
-        for (x, c) in row.iter().enumerate().filter(|&(_, &c)| c != '.') {
-            antennas
-                .entry(c)
-                .or_insert_with(HashSet::new)
-                .insert(point![x as i32, y as i32]);
+        AntennaMap {
+            width: map.first().map_or(0, |row| row.len() as i32),
+            height: map.len() as i32,
+            topology,
+            antennas,
         }
     }
 
-    // Search antinodes
-    let mut antinodes = HashSet::new();
-    let mut harmonic_antinodes = HashSet::new();
+    // Point as seen on the map, None if outside
+    fn locate(&self, point: &Point2<i32>) -> Option<Point2<i32>> {
+        match self.topology {
+            Topology::Bounded => {
+                let inside = (0..self.width).contains(&point.x) && (0..self.height).contains(&point.y);
+                inside.then_some(*point)
+            }
+            Topology::Toroidal => Some(point![point.x.rem_euclid(self.width), point.y.rem_euclid(self.height)]),
+        }
+    }
 
-    for antennas in antennas.values() {
-        for a in antennas {
-            for b in antennas {
-                if a == b {
-                    continue;
+    // Number of points start + n * step on the map before leaving it (or before repeating on a torus)
+    fn reach(&self, start: &Point2<i32>, step: &Vector2<i32>) -> usize {
+        match self.topology {
+            Topology::Bounded => {
+                if self.locate(start).is_none() {
+                    return 0;
                 }
 
-                let v = b - a;
+                let axis = |pos: i32, step: i32, size: i32| match step.signum() {
+                    1 => Some((size - 1 - pos) / step),
+                    -1 => Some(pos / -step),
+                    _ => None,
+                };
 
-                for (idx, &antinode) in search_antinodes(&map, *a, &(-v)).iter().enumerate() {
-                    if idx == 1 {
-                        antinodes.insert(antinode);
+                axis(start.x, step.x, self.width)
+                    .into_iter()
+                    .chain(axis(start.y, step.y, self.height))
+                    .min()
+                    .map_or(1, |steps| steps as usize + 1)
+            }
+            Topology::Toroidal => {
+                let period = |step: i32, size: i32| size / gcd(step.rem_euclid(size), size);
+                lcm(period(step.x, self.width), period(step.y, self.height)) as usize
+            }
+        }
+    }
+
+    // Points from start, stepping by vec, at most max + 1 of them
+    fn walk(&self, start: Point2<i32>, vec: Vector2<i32>, max: Option<u32>) -> Vec<Point2<i32>> {
+        let count = self.reach(&start, &vec).min(max.map_or(usize::MAX, |max| max as usize + 1));
+
+        (0..count as i32)
+            .filter_map(|n| self.locate(&(start + vec * n)))
+            .collect()
+    }
+
+    fn pair_antinodes(&self, a: Point2<i32>, b: Point2<i32>, rule: &AntinodeRule) -> Vec<Point2<i32>> {
+        let v = b - a;
+        let mut sides = Vec::new();
+
+        if rule.side != Side::Backward {
+            sides.push((b, v));
+        }
+
+        if rule.side != Side::Forward {
+            sides.push((a, -v));
+        }
+
+        sides.into_iter()
+            .flat_map(|(origin, dir)| match rule.spacing {
+                // Point origin + dir / (ratio - 1) is ratio times further from one antenna than the other
+                Spacing::Ratio(ratio) => {
+                    let div = ratio - 1;
+
+                    if div > 0 && dir.x % div == 0 && dir.y % div == 0 {
+                        self.locate(&(origin + dir / div)).into_iter().collect()
                     } else {
-                        harmonic_antinodes.insert(antinode);
+                        Vec::new()
                     }
                 }
+                Spacing::Harmonics(max) => self.walk(origin, dir, max),
+                Spacing::Line(max) => self.walk(origin, dir / gcd(dir.x, dir.y), max),
+            })
+            .chain(self.between(a, b, rule))
+            .collect()
+    }
 
-                for (idx, &antinode) in search_antinodes(&map, *b, &v).iter().enumerate() {
-                    if idx == 1 {
-                        antinodes.insert(antinode);
-                        harmonic_antinodes.insert(antinode);
-                    } else {
-                        harmonic_antinodes.insert(antinode);
+    // Grid points strictly between the pair, which lie on the line only when it extends both ways
+    fn between(&self, a: Point2<i32>, b: Point2<i32>, rule: &AntinodeRule) -> Vec<Point2<i32>> {
+        let v = b - a;
+        let div = gcd(v.x, v.y);
+
+        match rule.spacing {
+            Spacing::Line(_) if rule.side == Side::Both => (1..div).filter_map(|n| self.locate(&(a + v / div * n))).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn antinodes(&self, rule: &AntinodeRule) -> Antinodes {
+        let mut result = Antinodes::new();
+
+        for (&frequency, antennas) in &self.antennas {
+            let antinodes: &mut HashMap<_, _> = result.entry(frequency).or_default();
+
+            for (idx, a) in antennas.iter().enumerate() {
+                for b in &antennas[idx + 1..] {
+                    for antinode in self.pair_antinodes(*a, *b, rule) {
+                        let pairs: &mut Vec<_> = antinodes.entry(antinode).or_default();
+
+                        if !pairs.contains(&(*a, *b)) {
+                            pairs.push((*a, *b));
+                        }
                     }
                 }
             }
         }
+
+        result
     }
+}
 
-    // Print map with antinodes
-    for (y, row) in map.iter().enumerate() {
-        for (x, mut c) in row.iter().enumerate() {
-            let pt = point![x as i32, y as i32];
+fn gcd(a: i32, b: i32) -> i32 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
 
-            let mut style = Style::new();
+fn lcm(a: i32, b: i32) -> i32 {
+    a / gcd(a, b) * b
+}
 
-            if antinodes.contains(&pt) {
-                if c == &'.' {
-                    c = &'#';
-                }
+type Pair = (Point2<i32>, Point2<i32>);
+
+// Antinodes of each frequency, with the antenna pairs producing them
+type Antinodes = HashMap<char, HashMap<Point2<i32>, Vec<Pair>>>;
+
+fn union(antinodes: &Antinodes) -> HashSet<Point2<i32>> {
+    antinodes.values().flat_map(HashMap::keys).copied().collect()
+}
+
+/////////////////////////////////////////////////////////////////////
+// Solution
+/////////////////////////////////////////////////////////////////////
+const PART01_RULE: AntinodeRule = AntinodeRule { spacing: Spacing::Ratio(2), side: Side::Both };
+const PART02_RULE: AntinodeRule = AntinodeRule { spacing: Spacing::Harmonics(None), side: Side::Both };
+const LINE_RULE: AntinodeRule = AntinodeRule { spacing: Spacing::Line(None), side: Side::Both };
+
+struct Solution<'m> {
+    map: &'m AntennaMap,
+    antinodes: Antinodes,
+    harmonic_antinodes: Antinodes,
+}
+
+impl<'m> Solution<'m> {
+    fn solve(map: &'m AntennaMap, rule: &AntinodeRule, harmonic_rule: &AntinodeRule) -> Self {
+        Solution {
+            map,
+            antinodes: map.antinodes(rule),
+            harmonic_antinodes: map.antinodes(harmonic_rule),
+        }
+    }
+
+    fn frequencies(&self) -> Vec<char> {
+        let mut frequencies = self.map.antennas.keys().copied().collect::<Vec<_>>();
+        frequencies.sort();
+        frequencies
+    }
+
+    fn part01(&self) -> usize {
+        union(&self.antinodes).len()
+    }
+
+    fn part02(&self) -> usize {
+        union(&self.harmonic_antinodes).len()
+    }
+}
+
+/////////////////////////////////////////////////////////////////////
+// Rendering
+/////////////////////////////////////////////////////////////////////
+struct Rendering {
+    // Only show antennas and antinodes of this frequency
+    frequency: Option<char>,
+    legend: bool,
+}
 
-                style = style.color(AnsiColors::Yellow);
-            } else if harmonic_antinodes.contains(&pt) {
-                if c == &'.' {
-                    c = &'#';
+impl Rendering {
+    fn antinode_style() -> Style {
+        Style::new().color(AnsiColors::Yellow)
+    }
+
+    fn harmonic_style() -> Style {
+        Style::new().color(AnsiColors::Blue)
+    }
+
+    fn render(&self, solution: &Solution) -> Vec<String> {
+        let map = solution.map;
+        let shown = |antinodes: &Antinodes| -> HashSet<Point2<i32>> {
+            antinodes.iter()
+                .filter(|(frequency, _)| self.frequency.is_none_or(|shown| shown == **frequency))
+                .flat_map(|(_, antinodes)| antinodes.keys())
+                .copied()
+                .collect()
+        };
+
+        let antinodes = shown(&solution.antinodes);
+        let harmonic_antinodes = shown(&solution.harmonic_antinodes);
+
+        let mut cells = vec![vec!['.'; map.width as usize]; map.height as usize];
+
+        for (&frequency, antennas) in &map.antennas {
+            if self.frequency.is_none_or(|shown| shown == frequency) {
+                for antenna in antennas {
+                    cells[antenna.y as usize][antenna.x as usize] = frequency;
                 }
+            }
+        }
 
-                style = style.color(AnsiColors::Blue);
+        let mut lines = cells.iter()
+            .enumerate()
+            .map(|(y, row)| {
+                row.iter()
+                    .enumerate()
+                    .map(|(x, &c)| {
+                        let pt = point![x as i32, y as i32];
+                        let marked = antinodes.contains(&pt) || harmonic_antinodes.contains(&pt);
+                        let c = if c == '.' && marked { '#' } else { c };
+
+                        if antinodes.contains(&pt) {
+                            c.style(Self::antinode_style()).to_string()
+                        } else if harmonic_antinodes.contains(&pt) {
+                            c.style(Self::harmonic_style()).to_string()
+                        } else {
+                            c.to_string()
+                        }
+                    })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>();
+
+        if self.legend {
+            lines.push(String::new());
+            lines.push(format!("{} antinode", '#'.style(Self::antinode_style())));
+            lines.push(format!("{} harmonic antinode", '#'.style(Self::harmonic_style())));
+
+            for frequency in solution.frequencies() {
+                if self.frequency.is_none_or(|shown| shown == frequency) {
+                    lines.push(format!(
+                        "{frequency} {} antennas, {} antinodes, {} harmonic antinodes",
+                        map.antennas[&frequency].len(),
+                        solution.antinodes[&frequency].len(),
+                        solution.harmonic_antinodes[&frequency].len(),
+                    ));
+                }
             }
+        }
+
+        lines
+    }
+}
+
+/////////////////////////////////////////////////////////////////////
+// Report
+/////////////////////////////////////////////////////////////////////
+#[derive(Debug, Serialize)]
+struct AntinodeReport {
+    point: [i32; 2],
+    pairs: Vec<[[i32; 2]; 2]>,
+}
 
+#[derive(Debug, Serialize)]
+struct FrequencyReport {
+    frequency: char,
+    antennas: Vec<[i32; 2]>,
+    antinodes: Vec<AntinodeReport>,
+    harmonic_antinodes: Vec<AntinodeReport>,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    part01: usize,
+    part02: usize,
+    frequencies: Vec<FrequencyReport>,
+}
+
+fn coords(point: &Point2<i32>) -> [i32; 2] {
+    [point.x, point.y]
+}
 
-            print!("{}", c.style(style));
+fn antinode_reports(antinodes: &HashMap<Point2<i32>, Vec<Pair>>) -> Vec<AntinodeReport> {
+    let mut reports = antinodes.iter()
+        .map(|(point, pairs)| AntinodeReport {
+            point: coords(point),
+            pairs: pairs.iter().map(|(a, b)| [coords(a), coords(b)]).collect(),
+        })
+        .collect::<Vec<_>>();
+
+    reports.sort_by_key(|report| (report.point[1], report.point[0]));
+    reports
+}
+
+impl Report {
+    fn new(solution: &Solution) -> Self {
+        Report {
+            part01: solution.part01(),
+            part02: solution.part02(),
+            frequencies: solution.frequencies()
+                .into_iter()
+                .map(|frequency| FrequencyReport {
+                    frequency,
+                    antennas: solution.map.antennas[&frequency].iter().map(coords).collect(),
+                    antinodes: antinode_reports(&solution.antinodes[&frequency]),
+                    harmonic_antinodes: antinode_reports(&solution.harmonic_antinodes[&frequency]),
+                })
+                .collect(),
         }
+    }
+}
 
-        println!();
+/////////////////////////////////////////////////////////////////////
+// main
+/////////////////////////////////////////////////////////////////////
+fn main() {
+    let arg = |name: &str| std::env::args().find_map(|arg| arg.strip_prefix(name).map(str::to_string));
+    let flag = |name: &str| std::env::args().any(|arg| arg == name);
+    let topology = if flag("--wrap") { Topology::Toroidal } else { Topology::Bounded };
+
+    // Load map
+    let map: Vec<Vec<char>> = read_lines!("day-08/input.txt")
+        .map(|line| line.chars().collect())
+        .collect();
+
+    let antenna_map = AntennaMap::parse(&map, topology);
+
+    if let Some(spacing) = arg("--rule=") {
+        let rule = AntinodeRule::parse(&spacing, arg("--side=").as_deref());
+        let antinodes = antenna_map.antinodes(&rule);
+
+        let mut frequencies = antinodes.keys().collect::<Vec<_>>();
+        frequencies.sort();
+
+        for frequency in frequencies {
+            println!("{frequency}: {} antinodes", antinodes[frequency].len());
+        }
+
+        println!("total: {}", union(&antinodes).len());
+        return;
+    }
+
+    // Harmonics on every grid point of the antenna lines instead of antenna distance multiples
+    let harmonic_rule = if flag("--exact") { LINE_RULE } else { PART02_RULE };
+    let solution = Solution::solve(&antenna_map, &PART01_RULE, &harmonic_rule);
+
+    if flag("--json") {
+        println!("{}", serde_json::to_string_pretty(&Report::new(&solution)).unwrap());
+        return;
     }
 
-    println!("part 01: {}", antinodes.len());
-    println!("part 02: {}", harmonic_antinodes.len());
+    // Print map with antinodes
+    if flag("--render") || flag("--legend") || arg("--frequency=").is_some() {
+        let rendering = Rendering {
+            frequency: arg("--frequency=").and_then(|frequency| frequency.chars().next()),
+            legend: flag("--legend"),
+        };
+
+        for line in rendering.render(&solution) {
+            println!("{line}");
+        }
+    }
+
+    println!("part 01: {}", solution.part01());
+    println!("part 02: {}", solution.part02());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::vector;
+
+    const EXAMPLE: &str = "............
+........0...
+.....0......
+.......0....
+....0.......
+......A.....
+............
+............
+........A...
+.........A..
+............
+............";
+
+    fn example(topology: Topology) -> AntennaMap {
+        let map = EXAMPLE.lines().map(|line| line.chars().collect::<Vec<_>>()).collect::<Vec<_>>();
+        AntennaMap::parse(&map, topology)
+    }
+
+    #[test]
+    fn example_antinodes() {
+        let map = example(Topology::Bounded);
+
+        assert_eq!(union(&map.antinodes(&AntinodeRule::parse("ratio:2", None))).len(), 14);
+        assert_eq!(union(&map.antinodes(&AntinodeRule::parse("harmonics", None))).len(), 34);
+    }
+
+    #[test]
+    fn rule_variants() {
+        let map = AntennaMap::parse(&[".a.a....".chars().collect()], Topology::Bounded);
+
+        let antinodes = |spacing, side| union(&map.antinodes(&AntinodeRule::parse(spacing, side)));
+
+        assert_eq!(antinodes("ratio:2", None), HashSet::from([point![5, 0]]));
+        assert_eq!(antinodes("ratio:3", None), HashSet::from([point![0, 0], point![4, 0]]));
+        assert_eq!(antinodes("harmonics:1", Some("forward")), HashSet::from([point![3, 0], point![5, 0]]));
+        assert_eq!(antinodes("harmonics", Some("backward")), HashSet::from([point![1, 0]]));
+
+        let map = AntennaMap::parse(&[".a.a....".chars().collect()], Topology::Toroidal);
+        let antinodes = union(&map.antinodes(&AntinodeRule::parse("ratio:2", Some("backward"))));
+
+        assert_eq!(antinodes, HashSet::from([point![7, 0]]));
+    }
+
+    #[test]
+    fn line_rasterisation() {
+        let map = AntennaMap::parse(&["a.....".chars().collect(), "......".chars().collect(), "....a.".chars().collect(), "......".chars().collect()], Topology::Bounded);
+
+        let harmonics = union(&map.antinodes(&PART02_RULE));
+        assert_eq!(harmonics, HashSet::from([point![0, 0], point![4, 2]]));
+
+        let line = union(&map.antinodes(&LINE_RULE));
+        assert_eq!(line, HashSet::from([point![0, 0], point![2, 1], point![4, 2]]));
+
+        // With a side and a max, both spacings count from the same antenna and skip the points between
+        let map = AntennaMap::parse(&["a.........".chars().collect(), "..........".chars().collect(), "....a.....".chars().collect(), "..........".chars().collect(), "..........".chars().collect(), "..........".chars().collect()], Topology::Bounded);
+
+        let harmonics = union(&map.antinodes(&AntinodeRule { spacing: Spacing::Harmonics(Some(1)), side: Side::Forward }));
+        assert_eq!(harmonics, HashSet::from([point![4, 2], point![8, 4]]));
+
+        let line = union(&map.antinodes(&AntinodeRule { spacing: Spacing::Line(Some(1)), side: Side::Forward }));
+        assert_eq!(line, HashSet::from([point![4, 2], point![6, 3]]));
+
+        let line = union(&map.antinodes(&AntinodeRule { spacing: Spacing::Line(Some(2)), side: Side::Backward }));
+        assert_eq!(line, HashSet::from([point![0, 0]]));
+
+        let line = union(&map.antinodes(&AntinodeRule { spacing: Spacing::Line(Some(1)), side: Side::Both }));
+        assert_eq!(line, HashSet::from([point![0, 0], point![2, 1], point![4, 2], point![6, 3]]));
+    }
+
+    #[test]
+    fn arithmetic_reach() {
+        for topology in [Topology::Bounded, Topology::Toroidal] {
+            let map = AntennaMap { width: 5, height: 3, topology, antennas: HashMap::new() };
+
+            for (x, y, dx, dy) in (-1..6).flat_map(|x| (-1..4).flat_map(move |y| (-6..7).flat_map(move |dx| (-4..5).map(move |dy| (x, y, dx, dy))))) {
+                if dx == 0 && dy == 0 {
+                    continue;
+                }
+
+                let (start, step) = (point![x, y], vector![dx, dy]);
+                let mut seen = HashSet::new();
+                let walked = (0..)
+                    .map_while(|n| map.locate(&(start + step * n)))
+                    .take_while(|point| seen.insert(*point))
+                    .count();
+
+                assert_eq!(map.reach(&start, &step), walked, "{topology:?} {start} {step}");
+            }
+        }
+    }
+
+    #[test]
+    fn solution_pairs_and_rendering() {
+        let map = AntennaMap::parse(&["a.a.a.".chars().collect()], Topology::Bounded);
+        let solution = Solution::solve(&map, &PART01_RULE, &PART02_RULE);
+
+        assert_eq!(solution.antinodes[&'a'][&point![4, 0]], vec![(point![0, 0], point![2, 0])]);
+        assert_eq!(solution.harmonic_antinodes[&'a'][&point![2, 0]].len(), 2);
+
+        let rendering = Rendering { frequency: Some('b'), legend: false };
+        assert_eq!(rendering.render(&solution), vec!["......"]);
+
+        let report = Report::new(&solution);
+        assert_eq!((report.part01, report.part02), (2, 3));
+        assert_eq!(report.frequencies[0].antennas, vec![[0, 0], [2, 0], [4, 0]]);
+    }
 }
@@ -1,5 +1,6 @@
-use std::cmp::min;
-use std::iter::{zip, FusedIterator};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
+use std::iter::FusedIterator;
 
 macro_rules! read_lines {
     ($file:literal) => {{
@@ -19,13 +20,23 @@ struct FileSystem {
 }
 
 impl FileSystem {
+    fn parse(layout: &str) -> Self {
+        FileSystem {
+            layout: layout.chars().map(|c| (c as u8 - b'0') as u32).collect(),
+        }
+    }
+
+    fn render(&self) -> String {
+        render(&self.file_spans().collect::<Vec<_>>())
+    }
+
     fn file_blocks(&self) -> AmphipodBlockIterator<'_> {
         AmphipodBlockIterator {
             layout: &self.layout[..],
-            first_span_blocks: self.layout.first().copied(),
             first_span_id: 0,
-            last_span_blocks: self.layout.last().copied(),
-            last_span_id: self.layout.len() - 1,
+            front: 0,
+            back: 0,
+            len: self.disk_size(),
         }
     }
 
@@ -33,7 +44,6 @@ impl FileSystem {
         AmphipodSpanIterator {
             layout: &self.layout[..],
             first_span_id: 0,
-            last_span_id: self.layout.len() - 1,
         }
     }
 
@@ -46,7 +56,7 @@ impl FileSystem {
 // Amphipod Block
 /////////////////////////////////////////////////////////////////////
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 enum AmphipodBlock {
     File { id: usize },
     Empty,
@@ -56,38 +66,48 @@ enum AmphipodBlock {
 // Amphipod Block Iterator
 /////////////////////////////////////////////////////////////////////
 
-#[derive(Debug)]
+// Blocks of the spans in layout, minus the ones already taken from either end
+#[derive(Clone, Debug)]
 struct AmphipodBlockIterator<'a> {
     layout: &'a [u32],
-    first_span_blocks: Option<u32>,
     first_span_id: usize,
-    last_span_blocks: Option<u32>,
-    last_span_id: usize,
+    // Blocks taken from the front of the first span
+    front: u32,
+    // Blocks taken from the back of the last span
+    back: u32,
+    len: usize,
 }
 
 impl<'a> AmphipodBlockIterator<'a> {
-    fn first_span_blocks(&self) -> Option<&u32> {
-        if self.last_span_id == self.first_span_id {
-            zip(self.last_span_blocks.as_ref(), self.first_span_blocks.as_ref())
-                .next()
-                .map(|(first, last)| min(last, first))
-        } else {
-            self.first_span_blocks.as_ref()
+    fn first_span_blocks(&self) -> u32 {
+        match self.layout {
+            [] => 0,
+            [span] => span - self.front - self.back,
+            [span, ..] => span - self.front,
         }
     }
 
-    fn last_span_blocks(&self) -> Option<&u32> {
-        if self.last_span_id == self.first_span_id {
-            zip(self.last_span_blocks.as_ref(), self.first_span_blocks.as_ref())
-                .next()
-                .map(|(first, last)| min(last, first))
-        } else {
-            self.last_span_blocks.as_ref()
+    fn last_span_blocks(&self) -> u32 {
+        match self.layout {
+            [] => 0,
+            [span] => span - self.front - self.back,
+            [.., span] => span - self.back,
         }
     }
 
+    fn pop_first_span(&mut self) {
+        self.layout = &self.layout[1..];
+        self.first_span_id += 1;
+        self.front = 0;
+    }
+
+    fn pop_last_span(&mut self) {
+        self.layout = &self.layout[..self.layout.len() - 1];
+        self.back = 0;
+    }
+
     fn build_block(&self, id: usize) -> AmphipodBlock {
-        if id % 2 == 0 {
+        if id.is_multiple_of(2) {
             AmphipodBlock::File { id: id / 2 }
         } else {
             AmphipodBlock::Empty
@@ -99,58 +119,67 @@ impl<'a> Iterator for AmphipodBlockIterator<'a> {
     type Item = AmphipodBlock;
 
     fn next(&mut self) -> Option<Self::Item> {
+        self.nth(0)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+
+    // Skips whole spans at once
+    fn nth(&mut self, mut n: usize) -> Option<Self::Item> {
+        if n >= self.len {
+            self.len = 0;
+            return None;
+        }
+
         loop {
-            match self.first_span_blocks() {
-                Some(0) => {
-                    if self.layout.is_empty() {
-                        self.first_span_blocks = None;
-                        continue;
-                    }
+            let blocks = self.first_span_blocks() as usize;
 
-                    self.layout = &self.layout[1..];
-                    self.first_span_blocks = self.layout.first().copied();
+            if n < blocks {
+                self.front += n as u32 + 1;
+                self.len -= n + 1;
 
-                    self.first_span_id += 1;
-                }
-                Some(blocks) => {
-                    self.first_span_blocks = Some(blocks - 1);
-                    break Some(self.build_block(self.first_span_id));
-                }
-                None => break None
+                break Some(self.build_block(self.first_span_id));
             }
+
+            n -= blocks;
+            self.len -= blocks;
+            self.pop_first_span();
         }
     }
 }
 
 impl<'a> DoubleEndedIterator for AmphipodBlockIterator<'a> {
     fn next_back(&mut self) -> Option<Self::Item> {
-        loop {
-            match self.last_span_blocks() {
-                Some(0) => {
-                    if self.layout.is_empty() {
-                        self.last_span_blocks = None;
-                        continue;
-                    }
+        self.nth_back(0)
+    }
 
-                    self.layout = &self.layout[..self.layout.len() - 1];
-                    self.last_span_blocks = self.layout.last().copied();
+    fn nth_back(&mut self, mut n: usize) -> Option<Self::Item> {
+        if n >= self.len {
+            self.len = 0;
+            return None;
+        }
 
-                    if self.last_span_blocks.is_none() {
-                        break None;
-                    }
+        loop {
+            let blocks = self.last_span_blocks() as usize;
 
-                    self.last_span_id -= 1;
-                }
-                Some(blocks) => {
-                    self.last_span_blocks = Some(blocks - 1);
-                    break Some(self.build_block(self.last_span_id));
-                }
-                None => break None
+            if n < blocks {
+                self.back += n as u32 + 1;
+                self.len -= n + 1;
+
+                break Some(self.build_block(self.first_span_id + self.layout.len() - 1));
             }
+
+            n -= blocks;
+            self.len -= blocks;
+            self.pop_last_span();
         }
     }
 }
 
+impl<'a> ExactSizeIterator for AmphipodBlockIterator<'a> {}
+
 impl<'a> FusedIterator for AmphipodBlockIterator<'a> {}
 
 /////////////////////////////////////////////////////////////////////
@@ -164,14 +193,6 @@ enum AmphipodSpan {
 }
 
 impl AmphipodSpan {
-    fn is_empty(&self) -> bool {
-        matches!(self, AmphipodSpan::Empty { .. })
-    }
-
-    fn is_file(&self) -> bool {
-        matches!(self, AmphipodSpan::File { .. })
-    }
-
     fn id(&self) -> Option<&usize> {
         match self {
             AmphipodSpan::File { id, .. } => Some(id),
@@ -185,158 +206,753 @@ impl AmphipodSpan {
             AmphipodSpan::Empty { size } => size
         }
     }
-
-    fn size_mut(&mut self) -> &mut u32 {
-        match self {
-            AmphipodSpan::File { size, .. } => size,
-            AmphipodSpan::Empty { size } => size
-        }
-    }
 }
 
 /////////////////////////////////////////////////////////////////////
 // Amphipod Span Iterator
 /////////////////////////////////////////////////////////////////////
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 struct AmphipodSpanIterator<'a> {
     layout: &'a [u32],
     first_span_id: usize,
-    last_span_id: usize,
+}
+
+impl<'a> AmphipodSpanIterator<'a> {
+    fn build_span(&self, id: usize, size: u32) -> AmphipodSpan {
+        if id.is_multiple_of(2) {
+            AmphipodSpan::File { id: id / 2, size }
+        } else {
+            AmphipodSpan::Empty { size }
+        }
+    }
 }
 
 impl<'a> Iterator for AmphipodSpanIterator<'a> {
     type Item = AmphipodSpan;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if !self.layout.is_empty() {
-            let id = self.first_span_id;
-            let size = self.layout[0];
+        self.nth(0)
+    }
 
-            self.first_span_id += 1;
-            self.layout = &self.layout[1..];
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.layout.len(), Some(self.layout.len()))
+    }
 
-            if id % 2 == 0 {
-                Some(AmphipodSpan::File { id: id / 2, size })
-            } else {
-                Some(AmphipodSpan::Empty { size })
-            }
-        } else {
-            None
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        if n >= self.layout.len() {
+            self.first_span_id += self.layout.len();
+            self.layout = &[];
+            return None;
         }
+
+        let id = self.first_span_id + n;
+        let size = self.layout[n];
+
+        self.first_span_id += n + 1;
+        self.layout = &self.layout[n + 1..];
+
+        Some(self.build_span(id, size))
     }
 }
 
 impl<'a> DoubleEndedIterator for AmphipodSpanIterator<'a> {
     fn next_back(&mut self) -> Option<Self::Item> {
-        if !self.layout.is_empty() {
-            let id = self.last_span_id;
-            let size = self.layout.last().copied().unwrap();
+        self.nth_back(0)
+    }
+
+    fn nth_back(&mut self, n: usize) -> Option<Self::Item> {
+        if n >= self.layout.len() {
+            self.layout = &[];
+            return None;
+        }
+
+        let idx = self.layout.len() - 1 - n;
+        let size = self.layout[idx];
+
+        self.layout = &self.layout[..idx];
+
+        Some(self.build_span(self.first_span_id + idx, size))
+    }
+}
+
+impl<'a> ExactSizeIterator for AmphipodSpanIterator<'a> {}
+
+impl<'a> FusedIterator for AmphipodSpanIterator<'a> {}
+
+/////////////////////////////////////////////////////////////////////
+// Rendering
+/////////////////////////////////////////////////////////////////////
+
+// One char per block, '.' when free. Ids past 9 continue with letters and wrap around after 'z'.
+fn render(spans: &[AmphipodSpan]) -> String {
+    spans.iter()
+        .flat_map(|span| {
+            let c = span.id().map_or('.', |id| char::from_digit((id % 36) as u32, 36).unwrap());
+            std::iter::repeat_n(c, *span.size() as usize)
+        })
+        .collect()
+}
+
+// Dense digit format of the spans. File ids are implied by the position in that format, so files
+// get renumbered, and spans larger than 9 blocks are split around empty counterparts.
+fn serialize(spans: &[AmphipodSpan]) -> String {
+    let mut result = String::new();
+    let mut expect_file = true;
 
-            if self.last_span_id > 0 {
-                self.last_span_id -= 1;
+    for span in spans {
+        if span.id().is_some() != expect_file {
+            result.push('0');
+            expect_file = !expect_file;
+        }
+
+        let mut size = *span.size();
+
+        while size > 9 {
+            result.push_str("90");
+            size -= 9;
+        }
+
+        result.push(char::from_digit(size, 10).unwrap());
+        expect_file = !expect_file;
+    }
+
+    result
+}
+
+/////////////////////////////////////////////////////////////////////
+// Compaction
+/////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+struct Placement {
+    position: usize,
+    size: u32,
+    id: usize,
+}
+
+impl FileSystem {
+    fn disk_size(&self) -> usize {
+        self.layout().iter().map(|&size| size as usize).sum()
+    }
+
+    // Spans along with the position of their first block
+    fn positioned_spans(&self) -> impl Iterator<Item = (usize, AmphipodSpan)> + '_ {
+        self.file_spans().scan(0, |position, span| {
+            let start = *position;
+            *position += *span.size() as usize;
+
+            Some((start, span))
+        })
+    }
+
+    // Files and contiguous free runs (free spans only separated by empty files are merged)
+    fn placements(&self) -> (Vec<Placement>, Vec<(usize, usize)>) {
+        let mut placements = Vec::new();
+        let mut gaps: Vec<(usize, usize)> = Vec::new();
+        let mut contiguous = false;
+
+        for (position, span) in self.positioned_spans() {
+            match span {
+                AmphipodSpan::File { id, size } => {
+                    placements.push(Placement { position, size, id });
+                    contiguous &= size == 0;
+                }
+                AmphipodSpan::Empty { size: 0 } => {}
+                AmphipodSpan::Empty { size } => {
+                    match gaps.last_mut() {
+                        Some((_, gap)) if contiguous => *gap += size as usize,
+                        _ => gaps.push((position, size as usize)),
+                    }
+
+                    contiguous = true;
+                }
             }
+        }
+
+        (placements, gaps)
+    }
+}
+
+// Alternating file and free spans, as in the dense format (the first free span may be omitted)
+fn spans_from_placements(mut placements: Vec<Placement>, disk_size: usize) -> Vec<AmphipodSpan> {
+    placements.sort();
 
-            self.layout = &self.layout[..self.layout.len() - 1];
+    let mut spans = Vec::with_capacity(placements.len() * 2);
+    let mut position = 0;
+
+    for placement in placements {
+        // Empty files may sit in the middle of a file moved over them
+        let gap = placement.position.saturating_sub(position) as u32;
+
+        if !spans.is_empty() || gap > 0 {
+            spans.push(AmphipodSpan::Empty { size: gap });
+        }
+
+        spans.push(AmphipodSpan::File { id: placement.id, size: placement.size });
+        position = position.max(placement.position + placement.size as usize);
+    }
+
+    spans.push(AmphipodSpan::Empty { size: (disk_size - position) as u32 });
+    spans
+}
+
+fn checksum(spans: &[AmphipodSpan]) -> usize {
+    let mut result = 0;
+    let mut idx = 0;
 
-            if id % 2 == 0 {
-                Some(AmphipodSpan::File { id: id / 2, size })
-            } else {
-                Some(AmphipodSpan::Empty { size })
+    for span in spans {
+        if let Some(id) = span.id() {
+            result += id * (idx..idx + *span.size() as usize).sum::<usize>();
+        }
+
+        idx += *span.size() as usize;
+    }
+
+    result
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Compaction {
+    spans: Vec<AmphipodSpan>,
+    moves: usize,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Metrics {
+    checksum: usize,
+    moves: usize,
+    // Free runs followed by some file block
+    free_gaps: usize,
+    largest_free_span: usize,
+}
+
+impl Compaction {
+    fn metrics(&self) -> Metrics {
+        let mut free_gaps = 0;
+        let mut largest_free_span = 0;
+        let mut run = 0;
+
+        for span in &self.spans {
+            match span {
+                AmphipodSpan::File { size: 0, .. } => {}
+                AmphipodSpan::File { .. } => {
+                    free_gaps += usize::from(run > 0);
+                    run = 0;
+                }
+                AmphipodSpan::Empty { size } => {
+                    run += *size as usize;
+                    largest_free_span = largest_free_span.max(run);
+                }
             }
-        } else {
-            None
+        }
+
+        Metrics {
+            checksum: checksum(&self.spans),
+            moves: self.moves,
+            free_gaps,
+            largest_free_span,
         }
     }
 }
 
-impl<'a> FusedIterator for AmphipodSpanIterator<'a> {}
+/////////////////////////////////////////////////////////////////////
+// Strategies
+/////////////////////////////////////////////////////////////////////
+
+type Observer<'o> = Option<&'o mut dyn FnMut(&[AmphipodSpan])>;
+
+trait Strategy {
+    fn name(&self) -> &'static str;
+
+    // Calls observe with the intermediate layout after every move
+    fn compact_observed(&self, filesystem: &FileSystem, observe: Observer) -> Compaction;
+
+    fn compact(&self, filesystem: &FileSystem) -> Compaction {
+        self.compact_observed(filesystem, None)
+    }
+}
+
+// Moves blocks one by one from the end of the disk to the leftmost free block
+struct BlockCompaction;
+
+impl Strategy for BlockCompaction {
+    fn name(&self) -> &'static str {
+        "blocks"
+    }
+
+    fn compact_observed(&self, filesystem: &FileSystem, mut observe: Observer) -> Compaction {
+        let mut blocks = filesystem.file_blocks();
+        let mut placements: Vec<Placement> = Vec::new();
+        let mut position = 0;
+        let mut moves = 0;
+
+        while let Some(block) = blocks.next() {
+            let moved = matches!(block, AmphipodBlock::Empty);
+            let id = match block {
+                AmphipodBlock::File { id } => Some(id),
+                AmphipodBlock::Empty => {
+                    blocks.by_ref()
+                        .rev()
+                        .find_map(|block| match block {
+                            AmphipodBlock::File { id } => Some(id),
+                            AmphipodBlock::Empty => None,
+                        })
+                }
+            };
+
+            let Some(id) = id else { break };
+
+            moves += usize::from(moved);
+
+            match placements.last_mut() {
+                Some(last) if last.id == id => last.size += 1,
+                _ => placements.push(Placement { position, size: 1, id }),
+            }
+
+            position += 1;
+
+            if let (Some(observe), true) = (observe.as_mut(), moved) {
+                // Blocks left in the iterator are still where they started, right after the placed ones
+                let mut state = placements.clone();
+
+                for (offset, block) in blocks.clone().enumerate() {
+                    if let AmphipodBlock::File { id } = block {
+                        match state.last_mut() {
+                            Some(last) if last.id == id && last.position + last.size as usize == position + offset => last.size += 1,
+                            _ => state.push(Placement { position: position + offset, size: 1, id }),
+                        }
+                    }
+                }
+
+                observe(&spans_from_placements(state, filesystem.disk_size()));
+            }
+        }
+
+        Compaction {
+            spans: spans_from_placements(placements, filesystem.disk_size()),
+            moves,
+        }
+    }
+}
+
+// Moves each whole file once, highest id first, to the leftmost free run able to hold it.
+// Free runs are kept in one min-heap of positions per run size, so each move is logarithmic.
+struct MoveOnce;
+
+impl Strategy for MoveOnce {
+    fn name(&self) -> &'static str {
+        "move-once"
+    }
+
+    fn compact_observed(&self, filesystem: &FileSystem, mut observe: Observer) -> Compaction {
+        let (mut placements, gaps) = filesystem.placements();
+
+        let max_size = gaps.iter().map(|&(_, size)| size).max().unwrap_or(0);
+        let mut free = vec![BinaryHeap::new(); max_size + 1];
+        let mut moves = 0;
+
+        for (position, size) in gaps {
+            free[size].push(Reverse(position));
+        }
+
+        for idx in (0..placements.len()).rev() {
+            let file = &mut placements[idx];
+
+            if file.size == 0 {
+                continue;
+            }
+
+            let target = (file.size as usize..=max_size)
+                .filter_map(|size| free[size].peek().map(|&Reverse(position)| (position, size)))
+                .filter(|&(position, _)| position < file.position)
+                .min();
+
+            if let Some((position, size)) = target {
+                free[size].pop();
+
+                let remaining = size - file.size as usize;
+
+                if remaining > 0 {
+                    free[remaining].push(Reverse(position + file.size as usize));
+                }
+
+                file.position = position;
+                moves += 1;
+
+                if let Some(observe) = observe.as_mut() {
+                    observe(&spans_from_placements(placements.clone(), filesystem.disk_size()));
+                }
+            }
+        }
+
+        Compaction {
+            spans: spans_from_placements(placements, filesystem.disk_size()),
+            moves,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Fit {
+    First,
+    Best,
+    Worst,
+}
+
+// Free runs indexed both by position and by size, merged as space gets released
+#[derive(Debug, Default)]
+struct FreeSpace {
+    runs: BTreeMap<usize, usize>,
+    by_size: BTreeMap<usize, BTreeSet<usize>>,
+}
+
+impl FreeSpace {
+    fn remove(&mut self, position: usize) -> usize {
+        let size = self.runs.remove(&position).unwrap();
+        let positions = self.by_size.get_mut(&size).unwrap();
+
+        positions.remove(&position);
+
+        if positions.is_empty() {
+            self.by_size.remove(&size);
+        }
+
+        size
+    }
+
+    fn release(&mut self, mut position: usize, mut size: usize) {
+        if let Some((&before, &before_size)) = self.runs.range(..position).next_back() {
+            if before + before_size == position {
+                self.remove(before);
+                position = before;
+                size += before_size;
+            }
+        }
+
+        if self.runs.contains_key(&(position + size)) {
+            size += self.remove(position + size);
+        }
+
+        self.runs.insert(position, size);
+        self.by_size.entry(size).or_default().insert(position);
+    }
+
+    fn allocate(&mut self, position: usize, size: usize) {
+        let run = self.remove(position);
+
+        if run > size {
+            self.release(position + size, run - size);
+        }
+    }
+
+    // Position of a free run of at least size blocks starting before the given position
+    fn find(&self, fit: Fit, size: usize, before: usize) -> Option<usize> {
+        let mut candidates = self.by_size.range(size..)
+            .filter_map(|(_, positions)| positions.first().copied())
+            .filter(|&position| position < before);
+
+        match fit {
+            Fit::First => candidates.min(),
+            Fit::Best => candidates.next(),
+            Fit::Worst => candidates.next_back(),
+        }
+    }
+}
+
+// Moves whole files, highest id first, until no file can move left anymore
+struct FitCompaction {
+    fit: Fit,
+}
+
+impl Strategy for FitCompaction {
+    fn name(&self) -> &'static str {
+        match self.fit {
+            Fit::First => "first-fit",
+            Fit::Best => "best-fit",
+            Fit::Worst => "worst-fit",
+        }
+    }
+
+    fn compact_observed(&self, filesystem: &FileSystem, mut observe: Observer) -> Compaction {
+        let (mut placements, gaps) = filesystem.placements();
+        let mut free = FreeSpace::default();
+        let mut moves = 0;
+
+        for (position, size) in gaps {
+            free.release(position, size);
+        }
+
+        loop {
+            let mut moved = false;
+
+            for idx in (0..placements.len()).rev() {
+                let file = &mut placements[idx];
+                let size = file.size as usize;
+
+                if size == 0 {
+                    continue;
+                }
+
+                if let Some(position) = free.find(self.fit, size, file.position) {
+                    free.allocate(position, size);
+                    free.release(file.position, size);
+
+                    file.position = position;
+                    moves += 1;
+                    moved = true;
+
+                    if let Some(observe) = observe.as_mut() {
+                        observe(&spans_from_placements(placements.clone(), filesystem.disk_size()));
+                    }
+                }
+            }
+
+            if !moved {
+                break;
+            }
+        }
+
+        Compaction {
+            spans: spans_from_placements(placements, filesystem.disk_size()),
+            moves,
+        }
+    }
+}
+
+fn strategies() -> Vec<Box<dyn Strategy>> {
+    vec![
+        Box::new(BlockCompaction),
+        Box::new(FitCompaction { fit: Fit::First }),
+        Box::new(FitCompaction { fit: Fit::Best }),
+        Box::new(FitCompaction { fit: Fit::Worst }),
+        Box::new(MoveOnce),
+    ]
+}
 
 /////////////////////////////////////////////////////////////////////
 // main
 /////////////////////////////////////////////////////////////////////
 
 fn main() {
-    let filesystem = FileSystem {
-        layout: read_lines!("day-09/input.txt")
-            .next().unwrap()
-            .chars()
-            .map(|c| (c as u8 - b'0') as u32)
-            .collect::<Vec<_>>(),
-    };
+    let filesystem = FileSystem::parse(&read_lines!("day-09/input.txt").next().unwrap());
 
-    // let initialSize = filesystem.layout().iter().sum::<u32>();
-    // println!("initialSize: {initialSize}");
+    // Layout before, during and after compaction
+    if let Some(name) = std::env::args().find_map(|arg| arg.strip_prefix("--render=").map(str::to_string)) {
+        let strategies = strategies();
+        let strategy = strategies.iter()
+            .find(|strategy| strategy.name() == name)
+            .unwrap_or_else(|| panic!("unknown strategy {name}"));
+
+        println!("{}", filesystem.render());
+
+        let compaction = strategy.compact_observed(&filesystem, Some(&mut |spans| println!("{}", render(spans))));
+
+        println!("{}", render(&compaction.spans));
+        println!("{}", serialize(&compaction.spans));
+        return;
+    }
+
+    // Strategy comparison
+    if let Some(names) = std::env::args().find_map(|arg| arg.strip_prefix("--strategy=").map(str::to_string)) {
+        for strategy in strategies().iter().filter(|strategy| names == "all" || names.split(',').any(|name| name == strategy.name())) {
+            let metrics = strategy.compact(&filesystem).metrics();
+
+            println!(
+                "{:<10} checksum {:>16}  moves {:>6}  free gaps {:>6}  largest free span {:>6}",
+                strategy.name(), metrics.checksum, metrics.moves, metrics.free_gaps, metrics.largest_free_span,
+            );
+        }
+
+        return;
+    }
 
     // Part 01
-    let mut blocks = filesystem.file_blocks();
-    let mut idx = 0;
-    let mut part01 = 0;
+    let part01 = checksum(&BlockCompaction.compact(&filesystem).spans);
 
-    while let Some(block) = blocks.next() {
-        if let AmphipodBlock::File { id } = block {
-            part01 += id * idx;
-            idx += 1;
-        } else {
-            while let Some(block) = blocks.next_back() {
-                if let AmphipodBlock::File { id } = block {
-                    part01 += id * idx;
-                    idx += 1;
-                    break;
+    println!("part 01: {part01}");
+
+    // Part 02
+    let part02 = checksum(&MoveOnce.compact(&filesystem).spans);
+
+    println!("part 02: {part02}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pseudo random number below max, advancing state
+    fn xorshift(state: &mut u64, max: u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state % max
+    }
+
+    // Block by block model of the whole file compaction
+    fn naive_compact_files(filesystem: &FileSystem) -> usize {
+        let mut blocks = filesystem.file_spans()
+            .flat_map(|span| std::iter::repeat_n(span.id().copied(), *span.size() as usize))
+            .collect::<Vec<_>>();
+
+        for file in filesystem.file_spans().rev().filter(|span| span.id().is_some()) {
+            let size = *file.size() as usize;
+            let start = blocks.iter().position(|&block| block == file.id().copied()).unwrap_or(0);
+
+            let target = (0..start).find(|&position| blocks[position..position + size].iter().all(Option::is_none));
+
+            if let (Some(target), true) = (target, size > 0) {
+                for idx in 0..size {
+                    blocks[target + idx] = blocks[start + idx].take();
                 }
             }
         }
+
+        blocks.iter().enumerate().map(|(idx, block)| idx * block.unwrap_or(0)).sum()
     }
 
-    println!("part 01: {part01}");
+    // Every layout of up to 5 digits out of 0..=3
+    fn small_layouts() -> impl Iterator<Item = String> {
+        (1..=5u32).flat_map(|len| {
+            (0..4usize.pow(len)).map(move |n| (0..len).map(|digit| char::from_digit((n / 4usize.pow(digit) % 4) as u32, 10).unwrap()).collect())
+        })
+    }
 
-    // Part 02
-    let mut reworked = filesystem.file_spans().collect::<Vec<_>>();
-    // assert_eq!(initialSize, reworked.iter().map(|s| *s.size()).sum::<u32>());
-
-    for file in filesystem.file_spans().rev().filter(AmphipodSpan::is_file) {
-        let file_idx = reworked.iter().position(|x| x == &file).unwrap();
-
-        let span = reworked[..file_idx].iter()
-            .enumerate()
-            .filter(|(_, span)| span.is_empty())
-            .find(|(_, span)| span.size() >= file.size());
-
-        if let Some((idx, &span)) = span {
-            if idx == file_idx - 1 {
-                *reworked[file_idx + 1].size_mut() += *reworked[idx].size();
-                *reworked[idx].size_mut() = 0;
-            } else {
-                reworked.remove(file_idx);
-
-                if reworked.len() == file_idx {
-                    *reworked[file_idx - 1].size_mut() += file.size();
-                } else {
-                    *reworked[file_idx - 1].size_mut() += reworked[file_idx].size() + file.size();
-                    reworked.remove(file_idx);
+    fn model_nth<T: Copy>(model: &mut Vec<T>, back: bool, n: usize) -> Option<T> {
+        if n >= model.len() {
+            model.clear();
+            None
+        } else if back {
+            model.drain(model.len() - 1 - n..).next()
+        } else {
+            model.drain(..=n).next_back()
+        }
+    }
+
+    #[test]
+    fn iterators_match_expanded_model() {
+        for layout in small_layouts() {
+            let filesystem = FileSystem::parse(&layout);
+            let spans = filesystem.file_spans().collect::<Vec<_>>();
+            let blocks = spans.iter()
+                .flat_map(|span| {
+                    let block = span.id().map_or(AmphipodBlock::Empty, |&id| AmphipodBlock::File { id });
+                    std::iter::repeat_n(block, *span.size() as usize)
+                })
+                .collect::<Vec<_>>();
+
+            assert_eq!(filesystem.file_blocks().collect::<Vec<_>>(), blocks);
+            assert_eq!(filesystem.file_blocks().rev().collect::<Vec<_>>(), blocks.iter().rev().copied().collect::<Vec<_>>());
+            assert_eq!(filesystem.file_spans().rev().collect::<Vec<_>>(), spans.iter().rev().copied().collect::<Vec<_>>());
+
+            // Pseudo random mix of operations from both ends, checked against plain vectors
+            for seed in 1..=8u64 {
+                let mut rng = seed.wrapping_mul(0x9e3779b97f4a7c15);
+
+                let (mut model, mut iter) = (blocks.clone(), filesystem.file_blocks());
+                let (mut span_model, mut span_iter) = (spans.clone(), filesystem.file_spans());
+
+                for _ in 0..8 {
+                    let (back, n) = (xorshift(&mut rng, 2) == 1, xorshift(&mut rng, 4) as usize);
+
+                    let expected = model_nth(&mut model, back, n);
+                    assert_eq!(if back { iter.nth_back(n) } else { iter.nth(n) }, expected, "{layout} seed {seed}");
+                    assert_eq!(iter.len(), model.len(), "{layout} seed {seed}");
+
+                    let expected = model_nth(&mut span_model, back, n);
+                    assert_eq!(if back { span_iter.nth_back(n) } else { span_iter.nth(n) }, expected, "{layout} seed {seed}");
+                    assert_eq!(span_iter.len(), span_model.len(), "{layout} seed {seed}");
                 }
 
-                reworked.remove(idx);
-                reworked.insert(idx, AmphipodSpan::Empty { size: span.size() - file.size() });
-                reworked.insert(idx, file);
-                reworked.insert(idx, AmphipodSpan::Empty { size: 0 });
+                assert_eq!(iter.collect::<Vec<_>>(), model);
+                assert_eq!(span_iter.collect::<Vec<_>>(), span_model);
             }
         }
+    }
+
+    #[test]
+    fn example_compact_files() {
+        let filesystem = FileSystem::parse("2333133121414131402");
 
-        // assert_eq!(initialSize, reworked.iter().map(|s| *s.size()).sum::<u32>(), "error on {:?}", file.id());
+        assert_eq!(BlockCompaction.compact(&filesystem).metrics().checksum, 1928);
+        assert_eq!(MoveOnce.compact(&filesystem).metrics(), Metrics { checksum: 2858, moves: 4, free_gaps: 5, largest_free_span: 5 });
     }
 
-    let mut part02 = 0;
-    let mut idx = 0;
+    #[test]
+    fn compact_files_matches_naive() {
+        let mut seed = 0x2545f4914f6cdd1d_u64;
+
+        for len in 1..200 {
+            let layout = (0..len)
+                .map(|_| char::from_digit(xorshift(&mut seed, 10) as u32, 10).unwrap())
+                .collect::<String>();
 
-    for span in &reworked {
-        if let AmphipodSpan::File { id, .. } = span {
-            part02 += id * (idx..idx + *span.size() as usize).sum::<usize>();
+            let filesystem = FileSystem::parse(&layout);
+            assert_eq!(checksum(&MoveOnce.compact(&filesystem).spans), naive_compact_files(&filesystem), "{layout}");
         }
+    }
 
-        idx += *span.size() as usize;
+    #[test]
+    fn example_rendering() {
+        let filesystem = FileSystem::parse("2333133121414131402");
+        assert_eq!(filesystem.render(), "00...111...2...333.44.5555.6666.777.888899");
+
+        let mut steps = Vec::new();
+        let compaction = MoveOnce.compact_observed(&filesystem, Some(&mut |spans| steps.push(render(spans))));
+
+        assert_eq!(steps, vec![
+            "0099.111...2...333.44.5555.6666.777.8888..",
+            "0099.1117772...333.44.5555.6666.....8888..",
+            "0099.111777244.333....5555.6666.....8888..",
+            "00992111777.44.333....5555.6666.....8888..",
+        ]);
+        assert_eq!(render(&compaction.spans), steps[3]);
+
+        let mut steps = Vec::new();
+        let compaction = BlockCompaction.compact_observed(&FileSystem::parse("12345"), Some(&mut |spans| steps.push(render(spans))));
+
+        assert_eq!(compaction.moves, 5);
+
+        assert_eq!(steps, vec!["02.111....2222.", "022111....222..", "0221112...22...", "02211122..2....", "022111222......"]);
     }
 
-    // println!("{:?}", reworked.iter().map(|s| *s.size()).sum::<u32>());
-    println!("part 02: {part02}");
+    #[test]
+    fn serialize_round_trip() {
+        for layout in small_layouts().chain(["2333133121414131402".to_string()]) {
+            let filesystem = FileSystem::parse(&layout);
+            assert_eq!(serialize(&filesystem.file_spans().collect::<Vec<_>>()), layout);
+
+            // Compacted layouts keep their blocks once parsed back, only file ids change
+            for strategy in strategies() {
+                let compaction = strategy.compact(&filesystem);
+                let reparsed = FileSystem::parse(&serialize(&compaction.spans));
+
+                let occupancy = |picture: String| picture.chars().map(|c| c != '.').collect::<Vec<_>>();
+                assert_eq!(occupancy(reparsed.render()), occupancy(render(&compaction.spans)), "{layout} {}", strategy.name());
+            }
+        }
+
+        assert_eq!(serialize(&[AmphipodSpan::Empty { size: 2 }, AmphipodSpan::File { id: 3, size: 12 }, AmphipodSpan::File { id: 1, size: 1 }]), "0290301");
+    }
+
+    #[test]
+    fn strategies_keep_files() {
+        let filesystem = FileSystem::parse("2333133121414131402714");
+        let size = |spans: &[AmphipodSpan], id: usize| spans.iter().filter(|span| span.id() == Some(&id)).map(AmphipodSpan::size).sum::<u32>();
+
+        for strategy in strategies() {
+            let compaction = strategy.compact(&filesystem);
+
+            assert_eq!(compaction.spans.iter().map(|span| *span.size() as usize).sum::<usize>(), filesystem.disk_size());
+
+            for (id, &expected) in filesystem.layout().iter().step_by(2).enumerate() {
+                assert_eq!(size(&compaction.spans, id), expected, "{} file {id}", strategy.name());
+            }
+        }
+
+        let first_fit = FitCompaction { fit: Fit::First }.compact(&filesystem).metrics();
+        let move_once = MoveOnce.compact(&filesystem).metrics();
+        assert!(first_fit.moves >= move_once.moves);
+    }
 }
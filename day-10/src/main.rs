@@ -1,5 +1,4 @@
 use nalgebra::{point, vector, Point2, Vector2};
-use std::collections::{HashSet, VecDeque};
 
 macro_rules! read_lines {
     ($file:literal) => {{
@@ -9,75 +8,335 @@ macro_rules! read_lines {
     }};
 }
 
-const STEPS: [Vector2<i32>; 4] = [
+const STEPS: [Vector2<i32>; 8] = [
     vector![ 1,  0],
     vector![ 0,  1],
     vector![-1,  0],
     vector![ 0, -1],
+    vector![ 1,  1],
+    vector![-1,  1],
+    vector![-1, -1],
+    vector![ 1, -1],
 ];
 
-fn get_height<'a>(map: &'a [Vec<u8>], point: &Point2<i32>) -> Option<&'a u8> {
-    map.get(point.y as usize)?.get(point.x as usize)
+/////////////////////////////////////////////////////////////////////
+// Trail Rules
+/////////////////////////////////////////////////////////////////////
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct TrailRules {
+    // Height difference between consecutive cells of a trail
+    step: u8,
+    // Trails go down by step at every cell instead of up. Mixing climbs and descents within a trail is
+    // not supported, as trails could then loop back to a cell they already visited.
+    descending: bool,
+    diagonal: bool,
+    start: u8,
+    end: u8,
 }
 
-fn reachable_pics(map: &[Vec<u8>], start: &Point2<i32>) -> (usize, usize) {
-    let mut stack = VecDeque::from([*start]);
-    let mut results = HashSet::new();
-    let mut trails = 0;
-
-    while !stack.is_empty() {
-        let current = stack.pop_front().unwrap();
-        let height = get_height(map, &current).unwrap();
-
-        if height == &9 {
-            trails += 1;
-            results.insert(current);
-        } else {
-            for step in &STEPS {
-                let next = current + step;
-
-                if let Some(next_height) = get_height(map, &next) {
-                    if *next_height == height + 1 {
-                        stack.push_front(next);
+impl Default for TrailRules {
+    fn default() -> Self {
+        TrailRules { step: 1, descending: false, diagonal: false, start: 0, end: 9 }
+    }
+}
+
+impl TrailRules {
+    fn delta(&self) -> i32 {
+        if self.descending { -(self.step as i32) } else { self.step as i32 }
+    }
+
+    fn steps(&self) -> &'static [Vector2<i32>] {
+        if self.diagonal { &STEPS } else { &STEPS[..4] }
+    }
+
+    // Heights ordered so that every height comes after the ones trails go on to
+    fn sweep(&self) -> Vec<u8> {
+        if self.descending { (0..=9).collect() } else { (0..=9).rev().collect() }
+    }
+}
+
+/////////////////////////////////////////////////////////////////////
+// Topographic Map
+/////////////////////////////////////////////////////////////////////
+struct TopographicMap {
+    width: usize,
+    // None for impassable cells
+    heights: Vec<Option<u8>>,
+    rules: TrailRules,
+}
+
+impl TopographicMap {
+    fn parse<S: AsRef<str>>(lines: impl Iterator<Item = S>, rules: TrailRules) -> Self {
+        let mut width = None;
+        let mut heights = Vec::new();
+
+        for line in lines.filter(|line| !line.as_ref().is_empty()) {
+            let row = line.as_ref().chars().map(|c| c.to_digit(10).map(|height| height as u8)).collect::<Vec<_>>();
+            let width = *width.get_or_insert(row.len());
+
+            assert_eq!(row.len(), width, "map rows must all have the same length");
+            heights.extend(row);
+        }
+
+        let width = width.unwrap_or(0);
+
+        TopographicMap { width, heights, rules }
+    }
+
+    fn point(&self, idx: usize) -> Point2<i32> {
+        point![(idx % self.width) as i32, (idx / self.width) as i32]
+    }
+
+    fn index(&self, point: &Point2<i32>) -> Option<usize> {
+        if !(0..self.width as i32).contains(&point.x) || point.y < 0 {
+            return None;
+        }
+
+        let idx = point.y as usize * self.width + point.x as usize;
+        (idx < self.heights.len()).then_some(idx)
+    }
+
+    // Cells a trail can go on to from the given cell
+    fn moves(&self, idx: usize) -> impl Iterator<Item = usize> + '_ {
+        let current = self.point(idx);
+        let expected = self.heights[idx].map(|height| height as i32 + self.rules.delta());
+
+        self.rules.steps()
+            .iter()
+            .filter_map(move |step| self.index(&(current + step)))
+            .filter(move |&next| self.heights[next].map(i32::from) == expected)
+    }
+
+    // Cells of each height
+    fn levels(&self) -> Vec<Vec<usize>> {
+        let mut levels = vec![Vec::new(); 10];
+
+        for (idx, height) in self.heights.iter().enumerate() {
+            if let Some(height) = height {
+                levels[*height as usize].push(idx);
+            }
+        }
+
+        levels
+    }
+
+    fn trailheads(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.heights.len()).filter(|&idx| self.heights[idx] == Some(self.rules.start))
+    }
+
+    // Number of distinct trails from each cell to a trail end, summed level by level from the ends back
+    fn ratings(&self) -> Vec<usize> {
+        let levels = self.levels();
+        let mut ratings = vec![0; self.heights.len()];
+
+        for height in self.rules.sweep() {
+            for &idx in &levels[height as usize] {
+                ratings[idx] = if height == self.rules.end {
+                    1
+                } else {
+                    self.moves(idx).map(|next| ratings[next]).sum()
+                };
+            }
+        }
+
+        ratings
+    }
+
+    // Number of distinct trail ends reachable from each cell. Reachable ends are kept as bitsets,
+    // only for the levels that a cell still to be swept can step onto, at most step + 1 at any time.
+    fn scores(&self) -> Vec<usize> {
+        let levels = self.levels();
+        let ends = &levels[self.rules.end as usize];
+
+        let mut bit_of = vec![usize::MAX; self.heights.len()];
+
+        for (bit, &idx) in ends.iter().enumerate() {
+            bit_of[idx] = bit;
+        }
+
+        let words = ends.len().div_ceil(64);
+        let mut reachable = vec![Vec::new(); self.heights.len()];
+        let mut scores = vec![0; self.heights.len()];
+
+        for height in self.rules.sweep() {
+            for &idx in &levels[height as usize] {
+                let mut bits = vec![0u64; words];
+
+                if height == self.rules.end {
+                    bits[bit_of[idx] / 64] |= 1 << (bit_of[idx] % 64);
+                } else {
+                    for next in self.moves(idx) {
+                        for (word, other) in bits.iter_mut().zip(&reachable[next]) {
+                            *word |= other;
+                        }
                     }
                 }
+
+                scores[idx] = bits.iter().map(|word| word.count_ones() as usize).sum();
+                reachable[idx] = bits;
+            }
+
+            let next = height as i32 + self.rules.delta();
+
+            if let Some(done) = usize::try_from(next).ok().and_then(|next| levels.get(next)) {
+                for &idx in done {
+                    reachable[idx] = Vec::new();
+                }
             }
         }
-    }
 
-    (results.len(), trails)
+        scores
+    }
 }
 
 fn main() {
+    let arg = |name: &str| std::env::args().find_map(|arg| arg.strip_prefix(name).map(str::to_string));
+    let flag = |name: &str| std::env::args().any(|arg| arg == name);
+
+    // Trail rules, descending trails go from 9 down to 0 by default
+    let descending = flag("--descending");
+    let height = |name: &str, default: u8| arg(name).map_or(default, |height| height.parse().unwrap());
+
+    let rules = TrailRules {
+        step: height("--step=", 1),
+        descending,
+        diagonal: flag("--diagonal"),
+        start: height("--start=", if descending { 9 } else { 0 }),
+        end: height("--end=", if descending { 0 } else { 9 }),
+    };
+
+    // Trails with a zero step would stay on a single level, which the level sweep cannot order
+    assert!(rules.step > 0, "trail step must be at least 1");
+    assert!(rules.start <= 9 && rules.end <= 9, "trail start and end must be heights from 0 to 9");
+
     // Load map
-    let mut map = Vec::new();
-    let mut starts = HashSet::new();
+    let map = TopographicMap::parse(read_lines!("day-10/input.txt"), rules);
+
+    // Part 1
+    let scores = map.scores();
+    let part01 = map.trailheads().map(|idx| scores[idx]).sum::<usize>();
+
+    // Part 2
+    let ratings = map.ratings();
+    let part02 = map.trailheads().map(|idx| ratings[idx]).sum::<usize>();
+
+    println!("part 01: {part01}");
+    println!("part 02: {part02}");
+}
 
-    for (y, line) in read_lines!("day-10/input.txt").enumerate() {
-        let mut row = Vec::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
 
-        for (x, c) in line.chars().enumerate() {
-            row.push(c as u8 - b'0');
+    const EXAMPLE: &str = "89010123
+78121874
+87430965
+96549874
+45678903
+32019012
+01329801
+10456732";
 
-            if c == '0' {
-                starts.insert(point![x as i32, y as i32]);
+    fn totals(map: &str, rules: TrailRules) -> (usize, usize) {
+        let map = TopographicMap::parse(map.lines(), rules);
+        let (scores, ratings) = (map.scores(), map.ratings());
+
+        (map.trailheads().map(|idx| scores[idx]).sum(), map.trailheads().map(|idx| ratings[idx]).sum())
+    }
+
+    // Enumerates every trail from start, returning the distinct peaks reached and the trail count
+    fn reachable_peaks(map: &TopographicMap, start: usize) -> (usize, usize) {
+        let mut stack = vec![start];
+        let mut peaks = HashSet::new();
+        let mut trails = 0;
+
+        while let Some(current) = stack.pop() {
+            if map.heights[current] == Some(map.rules.end) {
+                trails += 1;
+                peaks.insert(current);
+            } else {
+                stack.extend(map.moves(current));
             }
         }
 
-        map.push(row);
+        (peaks.len(), trails)
     }
 
-    // Part 1
-    let mut part01 = 0;
-    let mut part02 = 0;
-    
-    for start in starts {
-        let (a, b) = reachable_pics(&map, &start);
-        
-        part01 += a;
-        part02 += b;
-    }
-    
-    println!("part 01: {part01}");
-    println!("part 02: {part02}");
+    #[test]
+    fn example_scores_and_ratings() {
+        assert_eq!(totals(EXAMPLE, TrailRules::default()), (36, 81));
+    }
+
+    #[test]
+    fn impassable_cells() {
+        assert_eq!(totals("..90..9\n...1.98\n...2..7\n6543456\n765.987\n876....\n987....", TrailRules::default()), (4, 13));
+        assert_eq!(totals(".....0.\n..4321.\n..5..2.\n..6543.\n..7..4.\n..8765.\n..9....", TrailRules::default()), (1, 3));
+        assert_eq!(totals("012345\n123456\n234567\n345678\n4.6789\n56789.", TrailRules::default()), (2, 227));
+    }
+
+    #[test]
+    fn blank_lines() {
+        assert_eq!(totals(&format!("\n{EXAMPLE}\n\n"), TrailRules::default()), (36, 81));
+    }
+
+    #[test]
+    #[should_panic(expected = "same length")]
+    fn ragged_rows() {
+        TopographicMap::parse(["0123", "45"].iter(), TrailRules::default());
+    }
+
+    #[test]
+    fn trail_rules() {
+        let descending = TrailRules { descending: true, start: 9, end: 0, ..TrailRules::default() };
+        assert_eq!(totals(EXAMPLE, descending).1, 81);
+
+        let step = TrailRules { step: 2, end: 4, ..TrailRules::default() };
+        assert_eq!(totals("02\n24\n1.", step), (1, 2));
+
+        let diagonal = TrailRules { diagonal: true, end: 2, ..TrailRules::default() };
+        assert_eq!(totals("0.\n.1\n2.", TrailRules { end: 2, ..TrailRules::default() }), (0, 0));
+        assert_eq!(totals("0.\n.1\n2.", diagonal), (1, 1));
+
+        let short = TrailRules { start: 3, end: 5, ..TrailRules::default() };
+        assert_eq!(totals("34\n45\n6.", short), (1, 2));
+    }
+
+    #[test]
+    fn sweep_matches_trail_enumeration() {
+        let mut seed = 0x853c49e6748fea9b_u64;
+
+        for size in [5, 17, 40, 90] {
+            // Mostly smooth slopes, so that long trails and many peaks show up
+            let lines = (0..size)
+                .map(|y| {
+                    (0..size)
+                        .map(|x| {
+                            seed ^= seed << 13;
+                            seed ^= seed >> 7;
+                            seed ^= seed << 17;
+
+                            let height = if seed.is_multiple_of(5) { seed / 5 % 10 } else { (x + y) as u64 % 10 };
+                            char::from_digit(height as u32, 10).unwrap()
+                        })
+                        .collect::<String>()
+                })
+                .collect::<Vec<_>>();
+
+            let variants = [
+                TrailRules::default(),
+                TrailRules { descending: true, start: 8, end: 1, diagonal: true, ..TrailRules::default() },
+                TrailRules { step: 3, end: 9, ..TrailRules::default() },
+            ];
+
+            for rules in variants {
+                let map = TopographicMap::parse(lines.iter(), rules);
+                let (scores, ratings) = (map.scores(), map.ratings());
+
+                for start in map.trailheads() {
+                    assert_eq!((scores[start], ratings[start]), reachable_peaks(&map, start), "{size} {start} {rules:?}");
+                }
+            }
+        }
+    }
 }